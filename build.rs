@@ -0,0 +1,12 @@
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    // Make the default linker script visible to `-Tlink.ld` in dependent crates
+    if env::var_os("CARGO_FEATURE_RT").is_some() {
+        let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+        fs::copy("link.ld", out.join("link.ld")).unwrap();
+        println!("cargo:rustc-link-search={}", out.display());
+    }
+    println!("cargo:rerun-if-changed=link.ld");
+    println!("cargo:rerun-if-changed=build.rs");
+}
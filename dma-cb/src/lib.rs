@@ -0,0 +1,422 @@
+//! Control blocks of the BCM283x and BCM2711 DMA engines.
+//!
+//! Only the encoding lives here, the engines themselves are driven by
+//! `hal::dma`, which re-exports everything. Keeping it apart lets the bit
+//! packing be tested on the host, e.g.
+//! `cargo test --target x86_64-unknown-linux-gnu`.
+
+#![cfg_attr(not(test), no_std)]
+
+// TI, legacy control blocks
+const TI_INTEN: u32 = 1 << 0;
+const TI_TDMODE: u32 = 1 << 1;
+const TI_WAIT_RESP: u32 = 1 << 3;
+const TI_DEST_INC: u32 = 1 << 4;
+const TI_DEST_WIDTH: u32 = 1 << 5;
+const TI_DEST_DREQ: u32 = 1 << 6;
+const TI_DEST_IGNORE: u32 = 1 << 7;
+const TI_SRC_INC: u32 = 1 << 8;
+const TI_SRC_WIDTH: u32 = 1 << 9;
+const TI_SRC_DREQ: u32 = 1 << 10;
+const TI_BURST_LENGTH: u32 = 12;
+const TI_PERMAP: u32 = 16;
+const TI_WAITS: u32 = 21;
+
+// TI, DMA4 control blocks
+const TI4_INTEN: u32 = 1 << 0;
+const TI4_TDMODE: u32 = 1 << 1;
+const TI4_WAIT_RESP: u32 = 1 << 2;
+const TI4_PERMAP: u32 = 9;
+const TI4_S_DREQ: u32 = 1 << 14;
+const TI4_D_DREQ: u32 = 1 << 15;
+// SRCI and DESTI
+const I4_INC: u32 = 1 << 12;
+const I4_SIZE_128: u32 = 2 << 13;
+const I4_IGNORE: u32 = 1 << 15;
+const I4_STRIDE: u32 = 16;
+
+/// Peripheral pacing a transfer, the `PERMAP` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dreq(pub u8);
+
+impl Dreq {
+    /// No pacing, the engine runs flat out
+    pub const NONE: Self = Dreq(0);
+    /// DSI0 on the BCM2837
+    pub const PWM1: Self = Dreq(1);
+    pub const PCM_TX: Self = Dreq(2);
+    pub const PCM_RX: Self = Dreq(3);
+    pub const SMI: Self = Dreq(4);
+    pub const PWM0: Self = Dreq(5);
+    pub const SPI0_TX: Self = Dreq(6);
+    pub const SPI0_RX: Self = Dreq(7);
+    pub const BSC_SLAVE_TX: Self = Dreq(8);
+    pub const BSC_SLAVE_RX: Self = Dreq(9);
+    pub const EMMC: Self = Dreq(11);
+    pub const UART0_TX: Self = Dreq(12);
+    pub const SDHOST: Self = Dreq(13);
+    pub const UART0_RX: Self = Dreq(14);
+    pub const DSI1: Self = Dreq(15);
+    pub const SPI1_TX: Self = Dreq(16);
+    pub const HDMI: Self = Dreq(17);
+    pub const SPI1_RX: Self = Dreq(18);
+}
+
+/// Which end of a transfer the DREQ paces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Writes to a peripheral FIFO wait for the peripheral
+    ToPeripheral,
+    /// Reads from a peripheral FIFO wait for data
+    FromPeripheral,
+}
+
+/// VideoCore bus address of ARM memory, for legacy engines and control
+/// block links. `None` above the first GB of SDRAM, which is all these
+/// engines can reach.
+#[inline]
+pub fn bus_address<T>(ptr: *const T) -> Option<u32> {
+    match ptr as usize {
+        addr if addr < 0x4000_0000 => Some(addr as u32 | 0xC000_0000),
+        _ => None,
+    }
+}
+
+/// DMA4 address of the peripheral at VideoCore bus address `bus`
+#[inline]
+pub fn dma4_peripheral_address(bus: u32) -> u64 {
+    0x4_0000_0000 | bus as u64
+}
+
+/// Legacy control block. Fields hold register values, see the builder
+/// methods.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, align(32))]
+pub struct ControlBlock {
+    pub ti: u32,
+    pub source: u32,
+    pub dest: u32,
+    pub length: u32,
+    pub stride: u32,
+    pub next: u32,
+    _reserved: [u32; 2],
+}
+
+impl ControlBlock {
+    /// Copies `len` bytes between bus addresses, with 128-bit accesses and
+    /// bursts of 4.
+    pub fn memcpy(source: u32, dest: u32, len: u32) -> Self {
+        ControlBlock {
+            ti: TI_SRC_INC
+                | TI_DEST_INC
+                | TI_SRC_WIDTH
+                | TI_DEST_WIDTH
+                | TI_WAIT_RESP
+                | 3 << TI_BURST_LENGTH,
+            source,
+            dest,
+            length: len,
+            ..Default::default()
+        }
+    }
+
+    /// Fills `len` bytes at `dest` with the word at `source`.
+    pub fn fill(source: u32, dest: u32, len: u32) -> Self {
+        ControlBlock {
+            ti: TI_DEST_INC | TI_WAIT_RESP,
+            source,
+            dest,
+            length: len,
+            ..Default::default()
+        }
+    }
+
+    /// Moves `len` bytes between memory and the FIFO of a peripheral, one
+    /// 32-bit word per access, paced by `dreq`.
+    pub fn peripheral(memory: u32, fifo: u32, len: u32, dreq: Dreq, direction: Direction) -> Self {
+        let (ti, source, dest) = match direction {
+            Direction::ToPeripheral => (TI_SRC_INC | TI_DEST_DREQ, memory, fifo),
+            Direction::FromPeripheral => (TI_DEST_INC | TI_SRC_DREQ, fifo, memory),
+        };
+        ControlBlock {
+            ti: ti | TI_WAIT_RESP | (dreq.0 as u32 & 0x1F) << TI_PERMAP,
+            source,
+            dest,
+            length: len,
+            ..Default::default()
+        }
+    }
+
+    /// Turns the block into `rows` transfers of `row_len` bytes, adding the
+    /// signed strides to the addresses between rows. Full engines only.
+    pub fn with_2d(
+        mut self,
+        row_len: u16,
+        rows: u16,
+        source_stride: i16,
+        dest_stride: i16,
+    ) -> Self {
+        assert!((1..=0x4000).contains(&rows));
+        self.ti |= TI_TDMODE;
+        self.length = ((rows - 1) as u32) << 16 | row_len as u32;
+        self.stride = (dest_stride as u16 as u32) << 16 | source_stride as u16 as u32;
+        self
+    }
+
+    /// Whether the block runs in 2D mode, which lite engines lack
+    pub fn is_2d(&self) -> bool {
+        self.ti & TI_TDMODE != 0
+    }
+
+    /// Raises the channel interrupt once the block completes.
+    pub fn with_interrupt(mut self) -> Self {
+        self.ti |= TI_INTEN;
+        self
+    }
+
+    /// Throws the written data away, e.g. draining an RX FIFO.
+    pub fn with_dest_ignored(mut self) -> Self {
+        self.ti |= TI_DEST_IGNORE;
+        self
+    }
+
+    /// Dummy cycles after each write, to slow the engine down
+    pub fn with_waits(mut self, cycles: u8) -> Self {
+        self.ti = self.ti & !(0x1F << TI_WAITS) | (cycles as u32 & 0x1F) << TI_WAITS;
+        self
+    }
+
+    /// Runs `next` once this block completes. A chain looping back to its
+    /// first block runs forever. Fails if `next` lies out of reach of the
+    /// legacy engines.
+    #[allow(clippy::result_unit_err)]
+    pub fn set_next(&mut self, next: Option<&ControlBlock>) -> Result<&mut Self, ()> {
+        self.next = match next {
+            Some(cb) => bus_address(cb).ok_or(())?,
+            None => 0,
+        };
+        Ok(self)
+    }
+}
+
+/// DMA4 control block, with 40-bit addresses
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, align(32))]
+pub struct Dma4ControlBlock {
+    pub ti: u32,
+    pub src: u32,
+    pub srci: u32,
+    pub dest: u32,
+    pub desti: u32,
+    pub len: u32,
+    /// Address shifted right by 5
+    pub next_cb: u32,
+    _reserved: u32,
+}
+
+impl Dma4ControlBlock {
+    fn new(ti: u32, source: u64, srci: u32, dest: u64, desti: u32, len: u32) -> Self {
+        Dma4ControlBlock {
+            ti: ti | TI4_WAIT_RESP,
+            src: source as u32,
+            srci: srci | (source >> 32) as u32 & 0xFF,
+            dest: dest as u32,
+            desti: desti | (dest >> 32) as u32 & 0xFF,
+            len,
+            ..Default::default()
+        }
+    }
+
+    /// Copies `len` bytes between physical addresses with 128-bit
+    /// accesses.
+    pub fn memcpy(source: u64, dest: u64, len: u32) -> Self {
+        let inc = I4_INC | I4_SIZE_128;
+        Self::new(0, source, inc, dest, inc, len)
+    }
+
+    /// Moves `len` bytes between memory and a peripheral FIFO, see
+    /// `dma4_peripheral_address`, paced by `dreq`.
+    pub fn peripheral(memory: u64, fifo: u64, len: u32, dreq: Dreq, direction: Direction) -> Self {
+        let permap = (dreq.0 as u32 & 0x1F) << TI4_PERMAP;
+        match direction {
+            Direction::ToPeripheral => Self::new(permap | TI4_D_DREQ, memory, I4_INC, fifo, 0, len),
+            Direction::FromPeripheral => {
+                Self::new(permap | TI4_S_DREQ, fifo, 0, memory, I4_INC, len)
+            }
+        }
+    }
+
+    /// Turns the block into `rows` transfers of `row_len` bytes, adding the
+    /// signed strides to the addresses between rows.
+    pub fn with_2d(
+        mut self,
+        row_len: u16,
+        rows: u16,
+        source_stride: i16,
+        dest_stride: i16,
+    ) -> Self {
+        assert!((1..=0x4000).contains(&rows));
+        self.ti |= TI4_TDMODE;
+        self.len = ((rows - 1) as u32) << 16 | row_len as u32;
+        self.srci = self.srci & 0xFFFF | (source_stride as u16 as u32) << I4_STRIDE;
+        self.desti = self.desti & 0xFFFF | (dest_stride as u16 as u32) << I4_STRIDE;
+        self
+    }
+
+    pub fn with_interrupt(mut self) -> Self {
+        self.ti |= TI4_INTEN;
+        self
+    }
+
+    pub fn with_dest_ignored(mut self) -> Self {
+        self.desti |= I4_IGNORE;
+        self
+    }
+
+    pub fn set_next(&mut self, next: Option<&Dma4ControlBlock>) -> &mut Self {
+        self.next_cb = next.map_or(0, |cb| (cb as *const _ as u64 >> 5) as u32);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_address_first_gb_only() {
+        assert_eq!(bus_address(core::ptr::null::<u8>()), Some(0xC000_0000));
+        assert_eq!(bus_address(0x3F00_1020 as *const u8), Some(0xFF00_1020));
+        assert_eq!(bus_address(0x4000_0000 as *const u8), None);
+        assert_eq!(bus_address(0x1_0000_0000u64 as usize as *const u8), None);
+    }
+
+    #[test]
+    fn dma4_peripheral() {
+        assert_eq!(dma4_peripheral_address(0x7E20_4004), 0x4_7E20_4004);
+    }
+
+    #[test]
+    fn memcpy() {
+        let cb = ControlBlock::memcpy(0xC000_1000, 0xC000_2000, 4096);
+        assert_eq!(cb.ti, 0x0000_3338);
+        assert_eq!(cb.source, 0xC000_1000);
+        assert_eq!(cb.dest, 0xC000_2000);
+        assert_eq!(cb.length, 4096);
+        assert_eq!(cb.stride, 0);
+        assert_eq!(cb.next, 0);
+        assert!(!cb.is_2d());
+    }
+
+    #[test]
+    fn peripheral_permap() {
+        let tx = ControlBlock::peripheral(
+            0xC000_1000,
+            0x7E20_4004,
+            64,
+            Dreq::SPI0_TX,
+            Direction::ToPeripheral,
+        );
+        assert_eq!(tx.ti, 6 << 16 | TI_SRC_INC | TI_DEST_DREQ | TI_WAIT_RESP);
+        assert_eq!((tx.source, tx.dest), (0xC000_1000, 0x7E20_4004));
+
+        let rx = ControlBlock::peripheral(
+            0xC000_1000,
+            0x7E20_4004,
+            64,
+            Dreq::SPI1_RX,
+            Direction::FromPeripheral,
+        )
+        .with_dest_ignored()
+        .with_waits(0x25);
+        assert_eq!(
+            rx.ti,
+            5 << 21 | 18 << 16 | TI_DEST_IGNORE | TI_SRC_DREQ | TI_DEST_INC | TI_WAIT_RESP
+        );
+        assert_eq!((rx.source, rx.dest), (0x7E20_4004, 0xC000_1000));
+    }
+
+    #[test]
+    fn two_d() {
+        let cb = ControlBlock::memcpy(0, 0, 0).with_2d(320, 240, -640, 1280);
+        assert!(cb.is_2d());
+        assert_eq!(cb.length, 239 << 16 | 320);
+        assert_eq!(cb.stride, 1280 << 16 | 0xFD80);
+    }
+
+    #[test]
+    #[should_panic]
+    fn two_d_no_rows() {
+        ControlBlock::default().with_2d(1, 0, 0, 0);
+    }
+
+    #[test]
+    fn link() {
+        let next = ControlBlock::default();
+        let mut cb = ControlBlock::default();
+        cb.set_next(None).unwrap();
+        assert_eq!(cb.next, 0);
+        // Host addresses are out of reach of the legacy engines
+        if (&next as *const ControlBlock as usize) < 0x4000_0000 {
+            cb.set_next(Some(&next)).unwrap();
+            assert_eq!(cb.next, bus_address(&next).unwrap());
+        } else {
+            assert!(cb.set_next(Some(&next)).is_err());
+        }
+    }
+
+    #[test]
+    fn dma4_memcpy() {
+        let cb = Dma4ControlBlock::memcpy(0x1_2345_6780, 0xAB_0000_0010, 4096);
+        assert_eq!(cb.ti, TI4_WAIT_RESP);
+        assert_eq!(cb.src, 0x2345_6780);
+        assert_eq!(cb.srci, 0x5000 | 0x01);
+        assert_eq!(cb.dest, 0x0000_0010);
+        assert_eq!(cb.desti, 0x5000 | 0xAB);
+        assert_eq!(cb.len, 4096);
+    }
+
+    #[test]
+    fn dma4_peripheral_permap() {
+        let fifo = dma4_peripheral_address(0x7E20_4004);
+        let tx =
+            Dma4ControlBlock::peripheral(0x1000, fifo, 64, Dreq::SPI0_TX, Direction::ToPeripheral);
+        assert_eq!(tx.ti, 6 << 9 | TI4_D_DREQ | TI4_WAIT_RESP);
+        assert_eq!((tx.srci, tx.desti), (I4_INC, 0x04));
+        assert_eq!(tx.dest, 0x7E20_4004);
+
+        let rx = Dma4ControlBlock::peripheral(
+            0x1000,
+            fifo,
+            64,
+            Dreq::UART0_RX,
+            Direction::FromPeripheral,
+        )
+        .with_dest_ignored()
+        .with_interrupt();
+        assert_eq!(rx.ti, 14 << 9 | TI4_S_DREQ | TI4_WAIT_RESP | TI4_INTEN);
+        assert_eq!((rx.srci, rx.desti), (0x04, I4_INC | I4_IGNORE));
+    }
+
+    #[test]
+    fn dma4_two_d() {
+        let cb = Dma4ControlBlock::memcpy(0x1_0000_0000, 0x2_0000_0000, 0)
+            .with_dest_ignored()
+            .with_2d(64, 16, -2, 512);
+        assert_eq!(cb.ti & TI4_TDMODE, TI4_TDMODE);
+        assert_eq!(cb.len, 15 << 16 | 64);
+        assert_eq!(cb.srci, 0xFFFE << 16 | 0x5000 | 0x01);
+        assert_eq!(cb.desti, 512 << 16 | I4_IGNORE | 0x5000 | 0x02);
+    }
+
+    #[test]
+    fn dma4_link() {
+        let next = Dma4ControlBlock::default();
+        let mut cb = Dma4ControlBlock::default();
+        cb.set_next(Some(&next));
+        // 40-bit addresses fit once shifted, wider host ones get truncated
+        assert_eq!(cb.next_cb, (&next as *const _ as u64 >> 5) as u32);
+        cb.set_next(None);
+        assert_eq!(cb.next_cb, 0);
+    }
+}
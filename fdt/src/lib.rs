@@ -0,0 +1,1087 @@
+//! Flattened device tree (DTB) parser.
+//!
+//! `no_std` and allocation free: everything borrows from the blob. Nodes
+//! only record where they start, so walking up to a parent rescans the
+//! structure block from the root, which is cheap for trees the size of
+//! the Raspberry Pi ones.
+//!
+//! The tests run on the host, e.g.
+//! `cargo test --target x86_64-unknown-linux-gnu`.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::str;
+
+const MAGIC: u32 = 0xD00D_FEED;
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Deepest nesting `Node::parent` can follow
+pub const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// A block or a token runs past the end of the blob
+    Truncated,
+    /// Unknown token, unbalanced nodes or non UTF-8 names
+    BadStructure,
+}
+
+#[inline]
+fn be32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(Error::Truncated)
+}
+
+#[inline]
+fn be64(data: &[u8], offset: usize) -> Result<u64, Error> {
+    Ok((be32(data, offset)? as u64) << 32 | be32(data, offset + 4)? as u64)
+}
+
+#[inline]
+fn align4(v: usize) -> usize {
+    (v + 3) & !3
+}
+
+fn c_str(data: &[u8], offset: usize) -> Result<(&str, usize), Error> {
+    let bytes = data.get(offset..).ok_or(Error::Truncated)?;
+    let len = bytes.iter().position(|&b| b == 0).ok_or(Error::Truncated)?;
+    let s = str::from_utf8(&bytes[..len]).map_err(|_| Error::BadStructure)?;
+    Ok((s, len))
+}
+
+/// Reads `cells` big-endian cells as one number, keeping the low 64 bits.
+fn read_cells(data: &[u8], cells: u32) -> Option<u64> {
+    let mut v = 0u64;
+    for i in 0..cells as usize {
+        v = v.checked_shl(32).unwrap_or(0) | be32(data, 4 * i).ok()? as u64;
+    }
+    Some(v)
+}
+
+#[derive(Clone, Copy)]
+enum Token<'a> {
+    Begin(&'a str),
+    End,
+    Prop(Property<'a>),
+    Finish,
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    reservations: usize,
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        if be32(data, 0)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let total = be32(data, 4)? as usize;
+        let data = data.get(..total).ok_or(Error::Truncated)?;
+
+        let off_struct = be32(data, 8)? as usize;
+        let off_strings = be32(data, 12)? as usize;
+        let off_reservations = be32(data, 16)? as usize;
+        let version = be32(data, 20)?;
+        let last_compatible = be32(data, 24)?;
+        if version < 17 || last_compatible > LAST_COMPATIBLE_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let size_strings = be32(data, 32)? as usize;
+        let size_struct = be32(data, 36)? as usize;
+
+        let fdt = Fdt {
+            data,
+            structs: data
+                .get(off_struct..off_struct + size_struct)
+                .ok_or(Error::Truncated)?,
+            strings: data
+                .get(off_strings..off_strings + size_strings)
+                .ok_or(Error::Truncated)?,
+            reservations: off_reservations,
+        };
+        match fdt.token(0)? {
+            (Token::Begin(_), _) => Ok(fdt),
+            _ => Err(Error::BadStructure),
+        }
+    }
+
+    /// Parses the blob at `ptr`, as passed by the firmware in `x0`.
+    ///
+    /// # Safety
+    ///
+    /// The whole blob (`totalsize` bytes) must stay mapped and unchanged
+    /// for `'a`.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, Error> {
+        let header = core::slice::from_raw_parts(ptr, 8);
+        if be32(header, 0)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let total = be32(header, 4)? as usize;
+        Self::new(core::slice::from_raw_parts(ptr, total))
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+
+    #[inline]
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Physical ranges listed in the memory reservation block
+    pub fn memory_reservations(&self) -> MemoryReservations<'a> {
+        MemoryReservations {
+            data: self.data,
+            offset: self.reservations,
+        }
+    }
+
+    pub fn root(&self) -> Node<'a> {
+        self.node_at(0, 0).unwrap()
+    }
+
+    /// Every node, depth first, root included
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+            done: false,
+        }
+    }
+
+    /// Looks a node up by path. Components without a unit address match
+    /// any unit address. Paths not starting with `/` begin with an alias,
+    /// which must name an absolute path: aliases are resolved once, never
+    /// through another alias.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        if let Some(rest) = path.strip_prefix('/') {
+            return self.root().descend(rest);
+        }
+        let (alias, rest) = path.split_at(path.find('/').unwrap_or(path.len()));
+        let target = self
+            .root()
+            .descend("aliases")?
+            .property(alias)?
+            .as_str()?
+            .strip_prefix('/')?;
+        self.root().descend(target)?.descend(rest)
+    }
+
+    /// Kernel command line, from `/chosen/bootargs`
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.find_node("/chosen")?.property("bootargs")?.as_str()
+    }
+
+    /// Root `model` property, e.g. "Raspberry Pi 4 Model B Rev 1.4"
+    pub fn model(&self) -> Option<&'a str> {
+        self.root().property("model")?.as_str()
+    }
+
+    /// Token at `offset` in the structure block, NOPs skipped, and the
+    /// offset of the next one.
+    fn token(&self, mut offset: usize) -> Result<(Token<'a>, usize), Error> {
+        loop {
+            let token = be32(self.structs, offset)?;
+            offset += 4;
+            return match token {
+                FDT_NOP => continue,
+                FDT_BEGIN_NODE => {
+                    let (name, len) = c_str(self.structs, offset)?;
+                    Ok((Token::Begin(name), align4(offset + len + 1)))
+                }
+                FDT_END_NODE => Ok((Token::End, offset)),
+                FDT_PROP => {
+                    let len = be32(self.structs, offset)? as usize;
+                    let name_offset = be32(self.structs, offset + 4)? as usize;
+                    let value = self
+                        .structs
+                        .get(offset + 8..offset + 8 + len)
+                        .ok_or(Error::Truncated)?;
+                    let (name, _) = c_str(self.strings, name_offset)?;
+                    Ok((
+                        Token::Prop(Property { name, value }),
+                        align4(offset + 8 + len),
+                    ))
+                }
+                FDT_END => Ok((Token::Finish, offset)),
+                _ => Err(Error::BadStructure),
+            };
+        }
+    }
+
+    fn node_at(&self, offset: usize, depth: usize) -> Option<Node<'a>> {
+        match self.token(offset).ok()? {
+            (Token::Begin(name), body) => Some(Node {
+                fdt: *self,
+                offset,
+                body,
+                name,
+                depth,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Offset of the token following the subtree whose body starts at
+    /// `offset`.
+    fn skip_subtree(&self, mut offset: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        loop {
+            let (token, next) = self.token(offset).ok()?;
+            offset = next;
+            match token {
+                Token::Begin(_) => depth += 1,
+                Token::End if depth == 0 => return Some(offset),
+                Token::End => depth -= 1,
+                Token::Prop(_) => (),
+                Token::Finish => return None,
+            }
+        }
+    }
+
+    fn parent_of(&self, node: &Node<'a>) -> Option<Node<'a>> {
+        if node.depth == 0 || node.depth > MAX_DEPTH {
+            return None;
+        }
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut offset = 0;
+        let mut depth = 0usize;
+        loop {
+            let (token, next) = self.token(offset).ok()?;
+            match token {
+                Token::Begin(_) => {
+                    if offset == node.offset {
+                        return self.node_at(stack[depth - 1], depth - 1);
+                    }
+                    if depth >= MAX_DEPTH {
+                        return None;
+                    }
+                    stack[depth] = offset;
+                    depth += 1;
+                }
+                Token::End => depth = depth.checked_sub(1)?,
+                Token::Prop(_) => (),
+                Token::Finish => return None,
+            }
+            offset = next;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MemoryReservations<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for MemoryReservations<'a> {
+    /// Address and size
+    type Item = (u64, u64);
+    fn next(&mut self) -> Option<(u64, u64)> {
+        let address = be64(self.data, self.offset).ok()?;
+        let size = be64(self.data, self.offset + 8).ok()?;
+        if address == 0 && size == 0 {
+            return None;
+        }
+        self.offset += 16;
+        Some((address, size))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    /// Offset of the FDT_BEGIN_NODE token
+    offset: usize,
+    /// Offset of the first token after the name
+    body: usize,
+    name: &'a str,
+    depth: usize,
+}
+
+impl<'a> Node<'a> {
+    /// Follows the `/` separated `path` down from this node
+    fn descend(self, path: &str) -> Option<Node<'a>> {
+        let mut node = self;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| {
+                child.name() == component
+                    || (!component.contains('@') && child.base_name() == component)
+            })?;
+        }
+        Some(node)
+    }
+
+    /// Full name, unit address included ("" for the root)
+    #[inline]
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Name without the unit address
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or("")
+    }
+
+    pub fn unit_address(&self) -> Option<&'a str> {
+        self.name.split('@').nth(1)
+    }
+
+    /// 0 for the root
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.body,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    pub fn children(&self) -> Children<'a> {
+        Children {
+            fdt: self.fdt,
+            offset: Some(self.body),
+            depth: self.depth + 1,
+        }
+    }
+
+    pub fn parent(&self) -> Option<Node<'a>> {
+        self.fdt.parent_of(self)
+    }
+
+    pub fn compatible(&self) -> StrList<'a> {
+        self.property("compatible")
+            .map(|p| p.strs())
+            .unwrap_or(StrList { data: &[] })
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// `#address-cells` of the children of this node
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|p| p.as_u32())
+            .unwrap_or(2)
+    }
+
+    /// `#size-cells` of the children of this node
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|p| p.as_u32())
+            .unwrap_or(1)
+    }
+
+    /// Entries of `reg`, as addresses of the parent bus.
+    pub fn reg(&self) -> Option<Reg<'a>> {
+        let parent = self.parent()?;
+        Some(Reg {
+            data: self.property("reg")?.value,
+            address_cells: parent.address_cells(),
+            size_cells: parent.size_cells(),
+        })
+    }
+
+    /// CPU physical address of the `index`th `reg` entry, after going
+    /// through the `ranges` of every bus above this node.
+    pub fn address(&self, index: usize) -> Option<u64> {
+        let region = self.reg()?.nth(index)?;
+        self.parent()?.translate(region.address)
+    }
+
+    /// Translates `address`, expressed in the address space of this
+    /// node's children, up to the root. `None` if a bus on the way has no
+    /// `ranges` or none of its ranges covers the address.
+    pub fn translate(&self, address: u64) -> Option<u64> {
+        let mut address = address;
+        let mut bus = *self;
+        while let Some(parent) = bus.parent() {
+            let ranges = bus.property("ranges")?;
+            if !ranges.value.is_empty() {
+                address = Ranges {
+                    data: ranges.value,
+                    child_cells: bus.address_cells(),
+                    parent_cells: parent.address_cells(),
+                    size_cells: bus.size_cells(),
+                }
+                .find_map(|r| r.translate(address))?;
+            }
+            bus = parent;
+        }
+        Some(address)
+    }
+}
+
+impl<'a> core::fmt::Debug for Node<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Node")
+            .field("name", &self.name)
+            .field("depth", &self.depth)
+            .finish()
+    }
+}
+
+/// Depth-first iterator over all nodes
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    depth: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+    fn next(&mut self) -> Option<Node<'a>> {
+        while !self.done {
+            let (token, next) = match self.fdt.token(self.offset) {
+                Ok(t) => t,
+                Err(_) => break,
+            };
+            let offset = self.offset;
+            self.offset = next;
+            match token {
+                Token::Begin(_) => {
+                    let node = self.fdt.node_at(offset, self.depth);
+                    self.depth += 1;
+                    return node;
+                }
+                Token::End => {
+                    self.depth -= 1;
+                    self.done = self.depth == 0;
+                }
+                Token::Prop(_) => (),
+                Token::Finish => break,
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    offset: Option<usize>,
+    depth: usize,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            let offset = self.offset?;
+            let (token, next) = self.fdt.token(offset).ok()?;
+            match token {
+                Token::Prop(_) => self.offset = Some(next),
+                Token::Begin(_) => {
+                    self.offset = self.fdt.skip_subtree(next);
+                    return self.fdt.node_at(offset, self.depth);
+                }
+                Token::End | Token::Finish => {
+                    self.offset = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+    fn next(&mut self) -> Option<Property<'a>> {
+        match self.fdt.token(self.offset).ok()? {
+            (Token::Prop(p), next) => {
+                self.offset = next;
+                Some(p)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Value as a single NUL-terminated string
+    pub fn as_str(&self) -> Option<&'a str> {
+        let (last, s) = self.value.split_last()?;
+        if *last != 0 {
+            return None;
+        }
+        str::from_utf8(s).ok()
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() != 4 {
+            return None;
+        }
+        be32(self.value, 0).ok()
+    }
+
+    /// One or two cells
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => be32(self.value, 0).ok().map(u64::from),
+            8 => be64(self.value, 0).ok(),
+            _ => None,
+        }
+    }
+
+    /// Value as a list of strings (`compatible`, `reg-names`…)
+    pub fn strs(&self) -> StrList<'a> {
+        StrList { data: self.value }
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+    }
+}
+
+pub struct StrList<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for StrList<'a> {
+    type Item = &'a str;
+    fn next(&mut self) -> Option<&'a str> {
+        let (s, len) = c_str(self.data, 0).ok()?;
+        self.data = &self.data[len + 1..];
+        Some(s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub address: u64,
+    /// `None` when the bus has `#size-cells = <0>`
+    pub size: Option<u64>,
+}
+
+pub struct Reg<'a> {
+    data: &'a [u8],
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = Region;
+    fn next(&mut self) -> Option<Region> {
+        let entry = 4 * (self.address_cells + self.size_cells) as usize;
+        if entry == 0 || self.data.len() < entry {
+            return None;
+        }
+        let address = read_cells(self.data, self.address_cells)?;
+        let size = match self.size_cells {
+            0 => None,
+            n => Some(read_cells(
+                &self.data[4 * self.address_cells as usize..],
+                n,
+            )?),
+        };
+        self.data = &self.data[entry..];
+        Some(Region { address, size })
+    }
+}
+
+struct Range {
+    child: u64,
+    parent: u64,
+    size: u64,
+}
+
+impl Range {
+    fn translate(&self, address: u64) -> Option<u64> {
+        let offset = address.checked_sub(self.child)?;
+        if offset < self.size {
+            self.parent.checked_add(offset)
+        } else {
+            None
+        }
+    }
+}
+
+struct Ranges<'a> {
+    data: &'a [u8],
+    child_cells: u32,
+    parent_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Ranges<'a> {
+    type Item = Range;
+    fn next(&mut self) -> Option<Range> {
+        let entry = 4 * (self.child_cells + self.parent_cells + self.size_cells) as usize;
+        if entry == 0 || self.data.len() < entry {
+            return None;
+        }
+        let child = read_cells(self.data, self.child_cells)?;
+        let data = &self.data[4 * self.child_cells as usize..];
+        let parent = read_cells(data, self.parent_cells)?;
+        let size = read_cells(&data[4 * self.parent_cells as usize..], self.size_cells)?;
+        self.data = &self.data[entry..];
+        Some(Range {
+            child,
+            parent,
+            size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal DTB writer for the tests
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+        reservations: Vec<(u64, u64)>,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            Builder {
+                structs: Vec::new(),
+                strings: Vec::new(),
+                reservations: Vec::new(),
+            }
+        }
+
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            while self.structs.len() & 3 != 0 {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.token(FDT_PROP);
+            self.structs
+                .extend_from_slice(&(value.len() as u32).to_be_bytes());
+            self.structs.extend_from_slice(&name_offset.to_be_bytes());
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn prop_str(&mut self, name: &str, value: &str) -> &mut Self {
+            let mut v = value.as_bytes().to_vec();
+            v.push(0);
+            self.prop(name, &v)
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let v: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.prop(name, &v)
+        }
+
+        fn reserve(&mut self, address: u64, size: u64) -> &mut Self {
+            self.reservations.push((address, size));
+            self
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+
+            let mut rsvmap = Vec::new();
+            for &(a, s) in self.reservations.iter().chain(&[(0, 0)]) {
+                rsvmap.extend_from_slice(&a.to_be_bytes());
+                rsvmap.extend_from_slice(&s.to_be_bytes());
+            }
+
+            let off_rsvmap = 40;
+            let off_struct = off_rsvmap + rsvmap.len();
+            let off_strings = off_struct + self.structs.len();
+            let total = off_strings + self.strings.len();
+
+            let mut blob = Vec::new();
+            for v in &[
+                MAGIC,
+                total as u32,
+                off_struct as u32,
+                off_strings as u32,
+                off_rsvmap as u32,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ] {
+                blob.extend_from_slice(&v.to_be_bytes());
+            }
+            blob.extend_from_slice(&rsvmap);
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// Cut down Raspberry Pi 4 tree
+    fn pi4() -> Vec<u8> {
+        let mut b = Builder::new();
+        b.reserve(0, 0x1000)
+            .begin("")
+            .prop_str("model", "Raspberry Pi 4 Model B Rev 1.4")
+            .prop("compatible", b"raspberrypi,4-model-b\0brcm,bcm2711\0")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[1]);
+        b.begin("aliases")
+            .prop_str("serial1", "/soc/serial@7e215040")
+            .prop_str("console", "serial1")
+            .prop_str("relative", "soc/gpio")
+            .prop_str("loop", "loop/soc")
+            .end();
+        b.begin("chosen")
+            .token(FDT_NOP)
+            .prop_str("bootargs", "console=ttyS0,115200 quiet")
+            .end();
+        b.begin("memory@0")
+            .prop_str("device_type", "memory")
+            .prop_cells("reg", &[0, 0, 0x3B40_0000])
+            .end();
+        b.begin("soc")
+            .prop_cells("#address-cells", &[1])
+            .prop_cells("#size-cells", &[1])
+            .prop_cells(
+                "ranges",
+                &[
+                    0x7E00_0000,
+                    0,
+                    0xFE00_0000,
+                    0x0180_0000, //
+                    0x7C00_0000,
+                    0,
+                    0xFC00_0000,
+                    0x0200_0000,
+                ],
+            );
+        b.begin("serial@7e215040")
+            .prop("compatible", b"brcm,bcm2835-aux-uart\0")
+            .prop_cells("reg", &[0x7E21_5040, 0x40])
+            .end();
+        b.begin("gpio@7e200000")
+            .prop_cells("reg", &[0x7E20_0000, 0xB4])
+            .end();
+        b.begin("unmapped@7a000000")
+            .prop_cells("reg", &[0x7A00_0000, 0x100])
+            .end();
+        b.end();
+        b.begin("scb")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[1]);
+        b.begin("nobus").prop_cells("reg", &[0, 0x1000, 0x10]).end();
+        b.end();
+        b.end();
+        b.finish()
+    }
+
+    #[test]
+    fn header_errors() {
+        let mut blob = pi4();
+        assert_eq!(Fdt::new(&blob[..20]).err(), Some(Error::Truncated));
+
+        blob[0] = 0;
+        assert_eq!(Fdt::new(&blob).err(), Some(Error::BadMagic));
+        blob[0] = 0xD0;
+
+        blob[23] = 2;
+        assert_eq!(Fdt::new(&blob).err(), Some(Error::UnsupportedVersion(2)));
+        blob[23] = 17;
+        assert!(Fdt::new(&blob).is_ok());
+
+        let len = blob.len();
+        assert_eq!(Fdt::new(&blob[..len - 1]).err(), Some(Error::Truncated));
+    }
+
+    #[test]
+    fn from_ptr() {
+        let blob = pi4();
+        let fdt = unsafe { Fdt::from_ptr(blob.as_ptr()) }.unwrap();
+        assert_eq!(fdt.total_size(), blob.len());
+        assert_eq!(fdt.as_ptr(), blob.as_ptr());
+    }
+
+    #[test]
+    fn walk_nodes() {
+        let blob = pi4();
+        let fdt = Fdt::new(&blob).unwrap();
+        let nodes: Vec<_> = fdt.nodes().map(|n| (n.name(), n.depth())).collect();
+        assert_eq!(
+            nodes,
+            [
+                ("", 0),
+                ("aliases", 1),
+                ("chosen", 1),
+                ("memory@0", 1),
+                ("soc", 1),
+                ("serial@7e215040", 2),
+                ("gpio@7e200000", 2),
+                ("unmapped@7a000000", 2),
+                ("scb", 1),
+                ("nobus", 2),
+            ]
+        );
+
+        let children: Vec<_> = fdt.root().children().map(|n| n.name()).collect();
+        assert_eq!(children, ["aliases", "chosen", "memory@0", "soc", "scb"]);
+    }
+
+    #[test]
+    fn properties() {
+        let blob = pi4();
+        let fdt = Fdt::new(&blob).unwrap();
+        let root = fdt.root();
+
+        let names: Vec<_> = root.properties().map(|p| p.name).collect();
+        assert_eq!(
+            names,
+            ["model", "compatible", "#address-cells", "#size-cells"]
+        );
+        assert_eq!(fdt.model(), Some("Raspberry Pi 4 Model B Rev 1.4"));
+        assert!(root.is_compatible("brcm,bcm2711"));
+        assert!(!root.is_compatible("brcm,bcm2837"));
+        assert_eq!(root.address_cells(), 2);
+        assert_eq!(root.property("#size-cells").unwrap().as_u64(), Some(1));
+        assert_eq!(root.property("model").unwrap().as_u32(), None);
+        assert!(root.property("missing").is_none());
+
+        let memory = fdt.find_node("/memory").unwrap();
+        let cells: Vec<_> = memory.property("reg").unwrap().cells().collect();
+        assert_eq!(cells, [0, 0, 0x3B40_0000]);
+    }
+
+    #[test]
+    fn lookup() {
+        let blob = pi4();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        assert_eq!(fdt.find_node("/").unwrap().name(), "");
+        assert_eq!(fdt.find_node("/soc/gpio").unwrap().name(), "gpio@7e200000");
+        assert_eq!(
+            fdt.find_node("/soc/gpio@7e200000").unwrap().unit_address(),
+            Some("7e200000")
+        );
+        assert!(fdt.find_node("/soc/gpio@7e200001").is_none());
+        assert!(fdt.find_node("/soc/missing").is_none());
+        assert_eq!(fdt.find_node("serial1").unwrap().base_name(), "serial");
+        assert!(fdt.find_node("serial0").is_none());
+        // Aliases name absolute paths, never other aliases
+        assert!(fdt.find_node("console").is_none());
+        assert!(fdt.find_node("relative").is_none());
+        assert!(fdt.find_node("loop").is_none());
+
+        assert_eq!(fdt.bootargs(), Some("console=ttyS0,115200 quiet"));
+    }
+
+    #[test]
+    fn parents() {
+        let blob = pi4();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        assert!(fdt.root().parent().is_none());
+        let serial = fdt.find_node("/soc/serial").unwrap();
+        let soc = serial.parent().unwrap();
+        assert_eq!(soc.name(), "soc");
+        assert_eq!(soc.parent().unwrap().name(), "");
+    }
+
+    #[test]
+    fn reg_and_ranges() {
+        let blob = pi4();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let memory = fdt.find_node("/memory").unwrap();
+        let reg: Vec<_> = memory.reg().unwrap().collect();
+        assert_eq!(
+            reg,
+            [Region {
+                address: 0,
+                size: Some(0x3B40_0000)
+            }]
+        );
+        assert_eq!(memory.address(0), Some(0));
+
+        let serial = fdt.find_node("/soc/serial").unwrap();
+        assert_eq!(
+            serial.reg().unwrap().next(),
+            Some(Region {
+                address: 0x7E21_5040,
+                size: Some(0x40)
+            })
+        );
+        assert_eq!(serial.address(0), Some(0xFE21_5040));
+        assert_eq!(serial.address(1), None);
+
+        let soc = fdt.find_node("/soc").unwrap();
+        assert_eq!(soc.translate(0x7E00_0000), Some(0xFE00_0000));
+        assert_eq!(soc.translate(0x7C00_0010), Some(0xFC00_0010));
+        assert_eq!(fdt.find_node("/soc/unmapped").unwrap().address(0), None);
+
+        // No `ranges` on /scb: not reachable from the CPU
+        assert_eq!(fdt.find_node("/scb/nobus").unwrap().address(0), None);
+    }
+
+    #[test]
+    fn ranges_overflow() {
+        let mut b = Builder::new();
+        b.begin("")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[2]);
+        b.begin("bus")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[2])
+            .prop_cells("ranges", &[0, 0, 0xFFFF_FFFF, 0xFFFF_F000, 0, 0x2000]);
+        b.begin("dev@1800")
+            .prop_cells("reg", &[0, 0x1800, 0, 4])
+            .end();
+        b.end();
+        b.end();
+        let blob = b.finish();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let bus = fdt.find_node("/bus").unwrap();
+        assert_eq!(bus.translate(0xFFF), Some(0xFFFF_FFFF_FFFF_FFFF));
+        assert_eq!(bus.translate(0x1000), None);
+        assert_eq!(fdt.find_node("/bus/dev").unwrap().address(0), None);
+    }
+
+    /// `bcm2711-rpi-4-b.dtb` of the Raspberry Pi firmware, from
+    /// https://github.com/raspberrypi/firmware/tree/master/boot
+    const PI4_FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/bcm2711-rpi-4-b.dtb"
+    );
+
+    #[test]
+    fn raspberry_pi_4_blob() {
+        let blob = match std::fs::read(PI4_FIXTURE) {
+            Ok(blob) => blob,
+            Err(_) => {
+                eprintln!("skipped, {} missing", PI4_FIXTURE);
+                return;
+            }
+        };
+        let fdt = Fdt::new(&blob).unwrap();
+        let root = fdt.root();
+        assert!(fdt.model().unwrap().starts_with("Raspberry Pi 4"));
+        assert!(root.is_compatible("brcm,bcm2711"));
+        assert_eq!(root.address_cells(), 2);
+
+        // #address-cells = <2> at the root
+        let memory = fdt.find_node("/memory").unwrap();
+        assert_eq!(memory.reg().unwrap().next().unwrap().address, 0);
+
+        // /soc maps the legacy 0x7E.. bus addresses
+        let soc = fdt.find_node("/soc").unwrap();
+        assert_eq!(soc.address_cells(), 1);
+        assert_eq!(soc.translate(0x7E00_0000), Some(0xFE00_0000));
+        assert_eq!(soc.translate(0x7C00_0000), Some(0xFC00_0000));
+        let gpio = fdt.find_node("/soc/gpio@7e200000").unwrap();
+        assert_eq!(
+            gpio.reg().unwrap().next(),
+            Some(Region {
+                address: 0x7E20_0000,
+                size: Some(0xB4)
+            })
+        );
+        assert_eq!(gpio.address(0), Some(0xFE20_0000));
+
+        // #address-cells = <2> on /scb
+        let genet = fdt.find_node("/scb/ethernet@7d580000").unwrap();
+        assert_eq!(genet.reg().unwrap().next().unwrap().address, 0x7D58_0000);
+        assert_eq!(genet.address(0), Some(0xFD58_0000));
+
+        // Both UARTs sit behind serial0 and serial1, in either order
+        for alias in &["serial0", "serial1"] {
+            let uart = fdt.find_node(alias).unwrap();
+            assert_eq!(uart.base_name(), "serial");
+            let address = uart.address(0).unwrap();
+            assert!(address == 0xFE20_1000 || address == 0xFE21_5040);
+        }
+        let aliases = fdt.find_node("/aliases").unwrap();
+        for alias in aliases.properties() {
+            if alias
+                .as_str()
+                .filter(|path| path.starts_with('/'))
+                .is_some()
+            {
+                assert!(fdt.find_node(alias.name).is_some(), "{}", alias.name);
+            }
+        }
+    }
+
+    #[test]
+    fn reservations() {
+        let blob = pi4();
+        let fdt = Fdt::new(&blob).unwrap();
+        let reserved: Vec<_> = fdt.memory_reservations().collect();
+        assert_eq!(reserved, [(0, 0x1000)]);
+    }
+
+    #[test]
+    fn bad_structure() {
+        let mut b = Builder::new();
+        b.begin("").token(0x42).end();
+        let blob = b.finish();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(fdt.nodes().count(), 1);
+        assert_eq!(fdt.root().children().count(), 0);
+
+        let mut b = Builder::new();
+        b.prop_str("orphan", "x");
+        assert_eq!(Fdt::new(&b.finish()).err(), Some(Error::BadStructure));
+    }
+}
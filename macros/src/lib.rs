@@ -0,0 +1,54 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Error, ItemFn, ReturnType, Type, Visibility};
+
+/// Marks the function the `rt` startup code jumps to on core 0.
+///
+/// It must be `fn() -> !`: no arguments, no generics, not `async`, `const`
+/// or `extern`.
+#[proc_macro_attribute]
+pub fn entry(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new(Span::call_site(), "`#[entry]` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let f = parse_macro_input!(input as ItemFn);
+
+    let diverges = match &f.sig.output {
+        ReturnType::Type(_, ty) => matches!(**ty, Type::Never(_)),
+        ReturnType::Default => false,
+    };
+    let valid = matches!(f.vis, Visibility::Inherited)
+        && f.sig.constness.is_none()
+        && f.sig.asyncness.is_none()
+        && f.sig.abi.is_none()
+        && f.sig.inputs.is_empty()
+        && f.sig.generics.params.is_empty()
+        && f.sig.generics.where_clause.is_none()
+        && f.sig.variadic.is_none()
+        && diverges;
+
+    if !valid {
+        return Error::new(
+            f.sig.span(),
+            "`#[entry]` function must have signature `[unsafe] fn() -> !`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let attrs = &f.attrs;
+    let unsafety = &f.sig.unsafety;
+    let ident = &f.sig.ident;
+    let block = &f.block;
+
+    quote!(
+        #(#attrs)*
+        #[export_name = "__hal_main"]
+        pub #unsafety fn #ident() -> ! #block
+    )
+    .into()
+}
@@ -0,0 +1,211 @@
+//! Legacy interrupt controllers: the ARMC (BCM2835-style on the Pi 3,
+//! per-core on the BCM2711) and the ARM local interrupt controller that
+//! muxes it with the core timers and mailboxes.
+
+use register::*;
+
+use super::{
+    board::{self, Board},
+    interrupt::{Controller, Irq},
+};
+
+const ARMC_OFFSET: usize = 0xB200;
+
+#[inline(always)]
+pub fn armc_base() -> usize {
+    super::peripherals_base() + ARMC_OFFSET
+}
+
+#[inline(always)]
+pub fn local_base() -> usize {
+    super::peripherals_base() + board::current().local_offset()
+}
+
+/// BCM2835-style controller of the Pi 3
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct ARMC2835Struct {
+    pub IRQ_BASIC_PENDING: Register<u32>,
+    pub IRQ_PENDING: [Register<u32>; 2],
+    pub FIQ_CONTROL: Register<u32>,
+    pub ENABLE_IRQS: [Register<u32>; 2],
+    pub ENABLE_BASIC_IRQS: Register<u32>,
+    pub DISABLE_IRQS: [Register<u32>; 2],
+    pub DISABLE_BASIC_IRQS: Register<u32>,
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct ARMCCore {
+    /// VideoCore 0–31, VideoCore 32–63, ARMC
+    pub PENDING: [Register<u32>; 3],
+    _reserved0: u32,
+    pub SET_EN: [Register<u32>; 3],
+    _reserved1: u32,
+    pub CLR_EN: [Register<u32>; 3],
+    _reserved2: [u32; 5],
+}
+
+/// Per-core controller of the BCM2711
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct ARMC2711Struct {
+    pub IRQ: [ARMCCore; 4],
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct LocalStruct {
+    _reserved0: [u32; 3],
+    pub GPU_INT_ROUTING: Register<u32>,
+    _reserved1: [u32; 12],
+    pub TIMER_CNTRL: [Register<u32>; 4],
+    pub MAILBOX_CNTRL: [Register<u32>; 4],
+    pub IRQ_SOURCE: [Register<u32>; 4],
+    pub FIQ_SOURCE: [Register<u32>; 4],
+}
+
+pub unsafe fn armc_2835<'a>() -> &'a mut ARMC2835Struct {
+    &mut *(armc_base() as *mut ARMC2835Struct)
+}
+
+pub unsafe fn armc_2711<'a>() -> &'a mut ARMC2711Struct {
+    &mut *(armc_base() as *mut ARMC2711Struct)
+}
+
+pub unsafe fn local<'a>() -> &'a mut LocalStruct {
+    &mut *(local_base() as *mut LocalStruct)
+}
+
+const LOCAL_SOURCE_GPU: u32 = 8;
+
+#[inline]
+fn lowest_bit(v: u32) -> Option<u32> {
+    if v == 0 {
+        None
+    } else {
+        Some(v.trailing_zeros())
+    }
+}
+
+pub struct ARMC;
+impl ARMC {
+    unsafe fn set_enabled(&mut self, irq: Irq, v: bool) {
+        match board::current() {
+            Board::RaspberryPi3 => self.set_enabled_2835(irq, v),
+            Board::RaspberryPi4 => self.set_enabled_2711(irq, v),
+        }
+    }
+
+    unsafe fn set_enabled_2835(&mut self, irq: Irq, v: bool) {
+        let armc = armc_2835();
+        let bit = 1 << (irq.0 % 32);
+        match irq.0 {
+            0..=63 if v => armc.ENABLE_IRQS[irq.0 as usize / 32].write(bit),
+            0..=63 => armc.DISABLE_IRQS[irq.0 as usize / 32].write(bit),
+            64..=71 if v => armc.ENABLE_BASIC_IRQS.write(bit),
+            64..=71 => armc.DISABLE_BASIC_IRQS.write(bit),
+            _ => self.set_local_enabled(irq, v),
+        }
+    }
+
+    unsafe fn set_enabled_2711(&mut self, irq: Irq, v: bool) {
+        let bit = 1 << (irq.0 % 32);
+        let core = &mut armc_2711().IRQ[super::core_id()];
+        match irq.0 {
+            0..=71 if v => core.SET_EN[irq.0 as usize / 32].write(bit),
+            0..=71 => core.CLR_EN[irq.0 as usize / 32].write(bit),
+            _ => self.set_local_enabled(irq, v),
+        }
+    }
+
+    unsafe fn set_local_enabled(&mut self, irq: Irq, v: bool) {
+        let core = super::core_id();
+        match irq.0 {
+            96..=99 => local().TIMER_CNTRL[core].set(irq.0 - 96, v),
+            100..=103 => local().MAILBOX_CNTRL[core].set(irq.0 - 100, v),
+            _ => {}
+        }
+    }
+
+    unsafe fn gpu_pending(&self) -> Option<Irq> {
+        match board::current() {
+            Board::RaspberryPi3 => self.gpu_pending_2835(),
+            Board::RaspberryPi4 => self.gpu_pending_2711(),
+        }
+    }
+
+    unsafe fn gpu_pending_2835(&self) -> Option<Irq> {
+        let armc = armc_2835();
+        if let Some(b) = lowest_bit(armc.IRQ_BASIC_PENDING.read() & 0xFF) {
+            return Some(Irq(64 + b));
+        }
+        for i in 0..2 {
+            if let Some(b) = lowest_bit(armc.IRQ_PENDING[i].read()) {
+                return Some(Irq(32 * i as u32 + b));
+            }
+        }
+        None
+    }
+
+    unsafe fn gpu_pending_2711(&self) -> Option<Irq> {
+        let core = &armc_2711().IRQ[super::core_id()];
+        if let Some(b) = lowest_bit(core.PENDING[2].read() & 0xFF) {
+            return Some(Irq(64 + b));
+        }
+        for i in 0..2 {
+            if let Some(b) = lowest_bit(core.PENDING[i].read()) {
+                return Some(Irq(32 * i as u32 + b));
+            }
+        }
+        None
+    }
+}
+
+impl Controller for ARMC {
+    unsafe fn init(&mut self) {
+        match board::current() {
+            Board::RaspberryPi3 => {
+                let armc = armc_2835();
+                armc.DISABLE_BASIC_IRQS.write(0xFFFF_FFFF);
+                armc.DISABLE_IRQS[0].write(0xFFFF_FFFF);
+                armc.DISABLE_IRQS[1].write(0xFFFF_FFFF);
+                // GPU IRQs to core 0
+                local().GPU_INT_ROUTING.write(0);
+            }
+            Board::RaspberryPi4 => {
+                for core in armc_2711().IRQ.iter_mut() {
+                    for clr in core.CLR_EN.iter_mut() {
+                        clr.write(0xFFFF_FFFF);
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn init_core(&mut self) {
+        let core = super::core_id();
+        local().TIMER_CNTRL[core].write(0);
+        local().MAILBOX_CNTRL[core].write(0);
+    }
+
+    unsafe fn enable(&mut self, irq: Irq) {
+        self.set_enabled(irq, true)
+    }
+
+    unsafe fn disable(&mut self, irq: Irq) {
+        self.set_enabled(irq, false)
+    }
+
+    unsafe fn next(&mut self) -> Option<Irq> {
+        let source = local().IRQ_SOURCE[super::core_id()].read();
+        match lowest_bit(source)? {
+            LOCAL_SOURCE_GPU => self.gpu_pending(),
+            b => Some(Irq(96 + b)),
+        }
+    }
+
+    unsafe fn complete(&mut self, _irq: Irq) {
+        // Legacy sources are level-triggered and cleared at the peripheral
+    }
+}
@@ -1,6 +1,11 @@
 use register::*;
 
-const AUX_BASE: usize = super::PERIPHERALS_BASE + 0x21_5000;
+const AUX_OFFSET: usize = 0x21_5000;
+
+#[inline(always)]
+pub fn aux_base() -> usize {
+    super::peripherals_base() + AUX_OFFSET
+}
 
 #[allow(non_snake_case)]
 #[repr(packed)]
@@ -18,17 +23,37 @@ pub struct MiniUartStruct {
     pub baud_rate: Register<u32>,
 }
 
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct SpiStruct {
+    pub CNTL0: Register<u32>,
+    pub CNTL1: Register<u32>,
+    pub STAT: Register<u32>,
+    pub PEEK: Register<u32>,
+    _reserved: [u32; 4],
+    /// Aliases of the same FIFO, the last write of a transfer goes here
+    pub IO: [Register<u32>; 4],
+    /// Like `IO` but keeps CS asserted after the shift
+    pub TXHOLD: [Register<u32>; 4],
+}
+
+/// `ENABLES` bits
+pub const ENABLE_MINI_UART: u32 = 0;
+pub const ENABLE_SPI1: u32 = 1;
+pub const ENABLE_SPI2: u32 = 2;
+
 #[allow(non_snake_case)]
 #[repr(packed)]
 pub struct AUXStruct {
     pub IRQ: Register<u32>,
     pub ENABLES: Register<u32>,
-    _reserved: [u32; 14],
+    _reserved0: [u32; 14],
     pub MU: MiniUartStruct,
+    _reserved1: [u32; 5],
+    /// SPI1 and SPI2
+    pub SPI: [SpiStruct; 2],
 }
 
-#[allow(non_upper_case_globals)]
-const AUXPtr: *mut AUXStruct = AUX_BASE as *mut AUXStruct;
 pub unsafe fn aux<'a>() -> &'a mut AUXStruct {
-    &mut *AUXPtr
+    &mut *(aux_base() as *mut AUXStruct)
 }
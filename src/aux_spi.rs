@@ -0,0 +1,313 @@
+//! SPI1 and SPI2, the two SPI masters of the AUX block.
+//!
+//! They are simpler than SPI0: 4-entry FIFOs, no DMA, and up to three
+//! chip selects driven by the controller itself. Transfers use the
+//! variable width mode, where each FIFO entry carries its own shift
+//! length (up to 24 bits), and go through `TXHOLD` for every entry but
+//! the last so that CS stays asserted for the whole transfer.
+//!
+//! Data is shifted MSB first. The clock is `core_clock / (2 * (speed + 1))`.
+
+use core::convert::Infallible;
+
+use embedded_hal::spi::{ErrorType, Mode, Phase, Polarity, SpiBus};
+
+use super::{
+    aux::{aux, SpiStruct, ENABLE_SPI1, ENABLE_SPI2},
+    board,
+    gpio::{Function, GPIO},
+};
+
+const FIFO_DEPTH: usize = 4;
+const MAX_SPEED: u32 = 0xFFF;
+
+// CNTL0
+const CNTL0_MSB_FIRST: u32 = 1 << 6;
+const CNTL0_CPOL: u32 = 1 << 7;
+const CNTL0_OUT_RISING: u32 = 1 << 8;
+const CNTL0_CLEAR_FIFOS: u32 = 1 << 9;
+const CNTL0_IN_RISING: u32 = 1 << 10;
+const CNTL0_ENABLE: u32 = 1 << 11;
+const CNTL0_VARIABLE_WIDTH: u32 = 1 << 14;
+const CNTL0_CS: u32 = 17;
+const CNTL0_SPEED: u32 = 20;
+
+// CNTL1
+const CNTL1_MSB_FIRST: u32 = 1 << 1;
+const CNTL1_CS_HIGH_TIME: u32 = 8;
+
+// STAT
+const STAT_BUSY: u32 = 6;
+const STAT_RX_EMPTY: u32 = 7;
+const STAT_TX_FULL: u32 = 10;
+
+/// Chip select line asserted (driven low) during transfers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipSelect {
+    Cs0 = 0,
+    Cs1 = 1,
+    Cs2 = 2,
+    /// All lines stay high, e.g. when CS is a GPIO handled by the caller
+    None = 3,
+}
+
+impl ChipSelect {
+    /// Pattern output on the three CS lines while a transfer runs
+    fn pattern(self) -> u32 {
+        match self {
+            ChipSelect::None => 0b111,
+            cs => 0b111 & !(1 << cs as u32),
+        }
+    }
+}
+
+/// `speed` field giving at most `hz` out of `clock`
+fn speed_for(clock: u32, hz: u32) -> u32 {
+    let divider = (clock + 2 * hz - 1) / (2 * hz);
+    (divider.max(1) - 1).min(MAX_SPEED)
+}
+
+pub struct AuxSpi {
+    index: usize,
+    cntl0: u32,
+    cntl1: u32,
+    clock: u32,
+}
+
+impl AuxSpi {
+    unsafe fn new(index: usize) -> Self {
+        let clock = board::current().core_clock();
+        AuxSpi {
+            index,
+            cntl0: CNTL0_ENABLE
+                | CNTL0_VARIABLE_WIDTH
+                | CNTL0_MSB_FIRST
+                | CNTL0_IN_RISING
+                | ChipSelect::Cs0.pattern() << CNTL0_CS
+                | speed_for(clock, 1_000_000) << CNTL0_SPEED,
+            cntl1: CNTL1_MSB_FIRST,
+            clock,
+        }
+    }
+
+    /// SPI1 on GPIO 16–21. Only one `AuxSpi` must exist per controller.
+    pub unsafe fn spi1() -> Self {
+        Self::new(0)
+    }
+
+    /// SPI2 on GPIO 40–45, only routed out on the Compute Modules. Only
+    /// one `AuxSpi` must exist per controller.
+    pub unsafe fn spi2() -> Self {
+        Self::new(1)
+    }
+
+    #[inline]
+    fn regs(&mut self) -> &mut SpiStruct {
+        unsafe { &mut aux().SPI[self.index] }
+    }
+
+    fn apply(&mut self) {
+        let (cntl0, cntl1) = (self.cntl0, self.cntl1);
+        let spi = self.regs();
+        unsafe {
+            spi.CNTL1.write(cntl1);
+            spi.CNTL0.write(cntl0);
+        }
+    }
+
+    /// Sets the CE2, CE1, CE0, MISO, MOSI and SCLK pins to ALT4.
+    pub unsafe fn init_pins(&mut self) -> &mut Self {
+        let first = if self.index == 0 { 16 } else { 40 };
+        for pin in first..first + 6 {
+            GPIO(pin).set_function(Function::Alternate4);
+        }
+        self
+    }
+
+    fn enable_bit(&self) -> u32 {
+        if self.index == 0 {
+            ENABLE_SPI1
+        } else {
+            ENABLE_SPI2
+        }
+    }
+
+    /// Powers the controller on and applies the configuration: mode 0,
+    /// CS0 and 1MHz unless changed.
+    pub fn enable(&mut self) -> &mut Self {
+        unsafe { aux().ENABLES.set(self.enable_bit(), true) };
+        let cntl0 = self.cntl0;
+        unsafe { self.regs().CNTL0.write(cntl0 | CNTL0_CLEAR_FIFOS) };
+        self.apply();
+        self
+    }
+
+    pub fn disable(&mut self) -> &mut Self {
+        unsafe {
+            self.regs().CNTL0.write(0);
+            aux().ENABLES.set(self.enable_bit(), false);
+        }
+        self
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) -> &mut Self {
+        let idle_high = mode.polarity == Polarity::IdleHigh;
+        let first_edge = mode.phase == Phase::CaptureOnFirstTransition;
+        // The first edge is rising when the clock idles low
+        let in_rising = idle_high != first_edge;
+
+        self.cntl0 &= !(CNTL0_CPOL | CNTL0_IN_RISING | CNTL0_OUT_RISING);
+        if idle_high {
+            self.cntl0 |= CNTL0_CPOL;
+        }
+        self.cntl0 |= if in_rising {
+            CNTL0_IN_RISING
+        } else {
+            CNTL0_OUT_RISING
+        };
+        self.apply();
+        self
+    }
+
+    /// Sets the fastest clock not above `hz`, from the core clock reported
+    /// by the firmware. See `frequency` for the resulting rate.
+    pub fn set_frequency(&mut self, hz: u32) -> &mut Self {
+        self.clock = board::core_clock();
+        self.cntl0 &= !(MAX_SPEED << CNTL0_SPEED);
+        self.cntl0 |= speed_for(self.clock, hz.max(1)) << CNTL0_SPEED;
+        self.apply();
+        self
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.clock / (2 * ((self.cntl0 >> CNTL0_SPEED) + 1))
+    }
+
+    pub fn set_chip_select(&mut self, cs: ChipSelect) -> &mut Self {
+        self.cntl0 &= !(0b111 << CNTL0_CS);
+        self.cntl0 |= cs.pattern() << CNTL0_CS;
+        self.apply();
+        self
+    }
+
+    /// Extra clock cycles (0 to 7) CS stays high between transfers.
+    pub fn set_cs_high_time(&mut self, cycles: u32) -> &mut Self {
+        self.cntl1 &= !(0b111 << CNTL1_CS_HIGH_TIME);
+        self.cntl1 |= (cycles & 0b111) << CNTL1_CS_HIGH_TIME;
+        self.apply();
+        self
+    }
+
+    pub fn is_busy(&mut self) -> bool {
+        unsafe { self.regs().STAT.get(STAT_BUSY) }
+    }
+
+    /// Shifts the low `bits` (1 to 24) of `value` out while shifting as
+    /// many bits in. With `keep_selected` CS stays asserted afterwards,
+    /// for a following shift of the same transfer.
+    pub fn transfer_bits(&mut self, value: u32, bits: u32, keep_selected: bool) -> u32 {
+        assert!(bits >= 1 && bits <= 24);
+        let mask = (1 << bits) - 1;
+        let data = bits << 24 | (value & mask) << (24 - bits);
+
+        let spi = self.regs();
+        unsafe {
+            while spi.STAT.get(STAT_TX_FULL) {}
+            if keep_selected {
+                spi.TXHOLD[0].write(data);
+            } else {
+                spi.IO[0].write(data);
+            }
+            while spi.STAT.get(STAT_RX_EMPTY) {}
+            spi.IO[0].read() & mask
+        }
+    }
+
+    /// Runs a `len` bytes transfer, taking outgoing bytes from `tx` and
+    /// giving incoming ones to `rx`. Bytes go by three per FIFO entry.
+    fn run<T, R>(&mut self, len: usize, mut tx: T, mut rx: R)
+    where
+        T: FnMut(usize) -> u8,
+        R: FnMut(usize, u8),
+    {
+        let spi = self.regs();
+        let mut sent = 0;
+        let mut received = 0;
+        let mut in_flight = 0;
+
+        while received < len {
+            while sent < len && in_flight < FIFO_DEPTH && unsafe { !spi.STAT.get(STAT_TX_FULL) } {
+                let count = (len - sent).min(3);
+                let mut data = (8 * count as u32) << 24;
+                for i in 0..count {
+                    data |= (tx(sent + i) as u32) << (8 * (2 - i));
+                }
+                sent += count;
+                unsafe {
+                    if sent < len {
+                        spi.TXHOLD[0].write(data);
+                    } else {
+                        spi.IO[0].write(data);
+                    }
+                }
+                in_flight += 1;
+            }
+
+            while in_flight > 0 && unsafe { !spi.STAT.get(STAT_RX_EMPTY) } {
+                let count = (len - received).min(3);
+                let data = unsafe { spi.IO[0].read() };
+                for i in 0..count {
+                    rx(received + i, (data >> (8 * (count - 1 - i))) as u8);
+                }
+                received += count;
+                in_flight -= 1;
+            }
+        }
+    }
+}
+
+impl ErrorType for AuxSpi {
+    type Error = Infallible;
+}
+
+impl SpiBus for AuxSpi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        self.run(words.len(), |_| 0, |i, b| words[i] = b);
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        self.run(words.len(), |i| words[i], |_, _| ());
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        let len = read.len().max(write.len());
+        self.run(
+            len,
+            |i| write.get(i).copied().unwrap_or(0),
+            |i, b| {
+                if let Some(r) = read.get_mut(i) {
+                    *r = b
+                }
+            },
+        );
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        // Byte `i` is always sent before it is received
+        let ptr = words.as_mut_ptr();
+        self.run(
+            words.len(),
+            |i| unsafe { *ptr.add(i) },
+            |i, b| unsafe { *ptr.add(i) = b },
+        );
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        while self.is_busy() {}
+        Ok(())
+    }
+}
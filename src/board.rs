@@ -0,0 +1,74 @@
+//! Board detection.
+//!
+//! The SoC is told apart by its cores: the BCM2837 (Pi 3, Zero 2 W) has
+//! Cortex-A53s, the BCM2711 (Pi 4, 400, CM4) Cortex-A72s. Any other core
+//! is treated as a BCM2711.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    RaspberryPi3,
+    RaspberryPi4,
+}
+
+const PART_CORTEX_A53: u64 = 0xD03;
+
+#[inline(always)]
+pub fn midr() -> u64 {
+    let midr: u64;
+    unsafe { asm!("mrs {}, midr_el1", out(reg) midr) };
+    midr
+}
+
+/// Primary part number of the core, e.g. 0xD08 for a Cortex-A72
+#[inline(always)]
+pub fn part_number() -> u64 {
+    (midr() >> 4) & 0xFFF
+}
+
+#[inline]
+pub fn current() -> Board {
+    match part_number() {
+        PART_CORTEX_A53 => Board::RaspberryPi3,
+        _ => Board::RaspberryPi4,
+    }
+}
+
+impl Board {
+    /// Default ARM address of the `0x7E00_0000` peripheral bus window
+    pub fn peripherals_base(self) -> usize {
+        match self {
+            Board::RaspberryPi3 => 0x3F00_0000,
+            Board::RaspberryPi4 => 0xFE00_0000,
+        }
+    }
+
+    /// Offset of the ARM local peripherals from the peripheral base
+    pub(crate) fn local_offset(self) -> usize {
+        match self {
+            Board::RaspberryPi3 => 0x100_0000,
+            Board::RaspberryPi4 => 0x180_0000,
+        }
+    }
+
+    /// VPU core clock with the firmware defaults and `enable_uart=1`, in
+    /// Hz. It drives the mini UART, the SPI and the I2C controllers.
+    pub fn core_clock(self) -> u32 {
+        match self {
+            Board::RaspberryPi3 => 250_000_000,
+            Board::RaspberryPi4 => 500_000_000,
+        }
+    }
+
+    pub fn has_gic(self) -> bool {
+        self == Board::RaspberryPi4
+    }
+}
+
+/// Core clock reported by the firmware, or the board default if the
+/// mailbox does not answer.
+pub fn core_clock() -> u32 {
+    match super::clock_rate(super::mailbox::Clock::Core) {
+        0 => current().core_clock(),
+        rate => rate,
+    }
+}
@@ -0,0 +1,292 @@
+//! BSC/SPI slave, to be driven by an external I2C or SPI master.
+//!
+//! The controller has 16-byte RX and TX FIFOs and no clock stretching:
+//! bytes a master reads must already sit in the TX FIFO, an empty FIFO
+//! shows up as an underrun. A full RX FIFO shows up as an overrun.
+//!
+//! Either poll the FIFOs with `read_byte`/`write_byte`, or implement
+//! `Handler` and call `service` from the loop or from the
+//! `Irq::BSC_SLAVE` handler after `enable_interrupts`.
+
+use embedded_hal::spi::{Mode as SpiMode, Phase, Polarity};
+use register::*;
+
+use super::{
+    board::{self, Board},
+    gpio::{Function, GPIO},
+};
+
+const BSC_SLAVE_OFFSET: usize = 0x21_4000;
+
+#[inline(always)]
+pub fn bsc_slave_base() -> usize {
+    super::peripherals_base() + BSC_SLAVE_OFFSET
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct BscSlaveStruct {
+    pub DR: Register<u32>,
+    pub RSR: Register<u32>,
+    pub SLV: Register<u32>,
+    pub CR: Register<u32>,
+    pub FR: Register<u32>,
+    pub IFLS: Register<u32>,
+    pub IMSC: Register<u32>,
+    pub RIS: Register<u32>,
+    pub MIS: Register<u32>,
+    pub ICR: Register<u32>,
+    pub DMACR: Register<u32>,
+    pub TDR: Register<u32>,
+    pub GPUSTAT: Register<u32>,
+    pub HCTRL: Register<u32>,
+}
+
+unsafe fn bsc_slave<'a>() -> &'a mut BscSlaveStruct {
+    &mut *(bsc_slave_base() as *mut BscSlaveStruct)
+}
+
+// CR
+const CR_EN: u32 = 1 << 0;
+const CR_SPI: u32 = 1 << 1;
+const CR_I2C: u32 = 1 << 2;
+const CR_CPHA: u32 = 1 << 3;
+const CR_CPOL: u32 = 1 << 4;
+const CR_BRK: u32 = 1 << 7;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+
+// FR
+const FR_TXBUSY: u32 = 0;
+const FR_RXFE: u32 = 1;
+const FR_TXFF: u32 = 2;
+const FR_RXFF: u32 = 3;
+const FR_TXFE: u32 = 4;
+const FR_RXBUSY: u32 = 5;
+const FR_TXFLEVEL: u32 = 6;
+const FR_RXFLEVEL: u32 = 11;
+
+// RSR, IMSC, RIS and ICR
+const RSR_OE: u32 = 1 << 0;
+const RSR_UE: u32 = 1 << 1;
+const INT_RX: u32 = 1 << 0;
+const INT_TX: u32 = 1 << 1;
+const INT_BREAK: u32 = 1 << 2;
+const INT_OVERRUN: u32 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// I2C slave at a 7-bit address
+    I2c(u8),
+    Spi(SpiMode),
+}
+
+/// Decoded `FR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    /// A master is reading
+    pub tx_busy: bool,
+    /// A master is writing
+    pub rx_busy: bool,
+    pub rx_empty: bool,
+    pub rx_full: bool,
+    pub tx_empty: bool,
+    pub tx_full: bool,
+    pub rx_level: u8,
+    pub tx_level: u8,
+}
+
+/// Sticky error flags from `RSR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Errors {
+    /// A byte was received with the RX FIFO full, and dropped
+    pub overrun: bool,
+    /// A master read with the TX FIFO empty
+    pub underrun: bool,
+}
+
+impl Errors {
+    pub fn any(&self) -> bool {
+        self.overrun || self.underrun
+    }
+}
+
+/// Callbacks for `BscSlave::service`
+pub trait Handler {
+    /// A byte written by the master
+    fn on_receive(&mut self, byte: u8);
+    /// Next byte to queue for the master to read, `None` to stop filling
+    /// the TX FIFO for now.
+    fn on_transmit(&mut self) -> Option<u8>;
+    fn on_error(&mut self, _errors: Errors) {}
+}
+
+pub struct BscSlave;
+
+impl BscSlave {
+    /// Only one `BscSlave` must exist.
+    pub unsafe fn new(mode: Mode) -> Self {
+        let mut slave = BscSlave;
+        slave.set_mode(mode);
+        slave
+    }
+
+    /// Muxes SDA/MOSI, SCL/SCLK, MISO and CE: GPIO 8–11 on the BCM2711,
+    /// 18–21 on the BCM2837, all ALT3.
+    pub unsafe fn init_pins(&mut self) -> &mut Self {
+        let first = match board::current() {
+            Board::RaspberryPi3 => 18,
+            Board::RaspberryPi4 => 8,
+        };
+        for pin in first..first + 4 {
+            GPIO(pin).set_function(Function::Alternate3);
+        }
+        self
+    }
+
+    /// Reconfigures and enables the controller, flushing both FIFOs.
+    pub fn set_mode(&mut self, mode: Mode) -> &mut Self {
+        let regs = unsafe { bsc_slave() };
+        let cr = match mode {
+            Mode::I2c(address) => {
+                unsafe { regs.SLV.write(address as u32 & 0x7F) };
+                CR_I2C
+            }
+            Mode::Spi(mode) => {
+                let mut cr = CR_SPI;
+                if mode.polarity == Polarity::IdleHigh {
+                    cr |= CR_CPOL;
+                }
+                if mode.phase == Phase::CaptureOnSecondTransition {
+                    cr |= CR_CPHA;
+                }
+                cr
+            }
+        };
+        unsafe {
+            regs.CR.write(0);
+            // BRK clears the FIFOs
+            regs.CR.write(CR_BRK);
+            regs.RSR.write(0);
+            regs.CR.write(cr | CR_EN | CR_TXE | CR_RXE);
+        }
+        self
+    }
+
+    pub fn set_address(&mut self, address: u8) -> &mut Self {
+        unsafe { bsc_slave().SLV.write(address as u32 & 0x7F) };
+        self
+    }
+
+    pub fn address(&self) -> u8 {
+        unsafe { bsc_slave().SLV.read() as u8 & 0x7F }
+    }
+
+    pub fn disable(&mut self) -> &mut Self {
+        unsafe { bsc_slave().CR.write(0) };
+        self
+    }
+
+    pub fn status(&self) -> Status {
+        let fr = unsafe { bsc_slave().FR.read() };
+        let bit = |b: u32| fr & (1 << b) != 0;
+        Status {
+            tx_busy: bit(FR_TXBUSY),
+            rx_busy: bit(FR_RXBUSY),
+            rx_empty: bit(FR_RXFE),
+            rx_full: bit(FR_RXFF),
+            tx_empty: bit(FR_TXFE),
+            tx_full: bit(FR_TXFF),
+            rx_level: ((fr >> FR_RXFLEVEL) & 0x1F) as u8,
+            tx_level: ((fr >> FR_TXFLEVEL) & 0x1F) as u8,
+        }
+    }
+
+    pub fn errors(&self) -> Errors {
+        let rsr = unsafe { bsc_slave().RSR.read() };
+        Errors {
+            overrun: rsr & RSR_OE != 0,
+            underrun: rsr & RSR_UE != 0,
+        }
+    }
+
+    pub fn clear_errors(&mut self) -> &mut Self {
+        unsafe {
+            bsc_slave().RSR.write(0);
+            bsc_slave().ICR.write(INT_OVERRUN | INT_BREAK);
+        }
+        self
+    }
+
+    /// Drops anything left in the TX FIFO, e.g. after an aborted read.
+    pub fn flush_tx(&mut self) -> &mut Self {
+        unsafe {
+            let cr = bsc_slave().CR.read();
+            bsc_slave().CR.write(cr | CR_BRK);
+            bsc_slave().CR.write(cr);
+        }
+        self
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            if bsc_slave().FR.get(FR_RXFE) {
+                None
+            } else {
+                Some(bsc_slave().DR.read() as u8)
+            }
+        }
+    }
+
+    /// Queues a byte for the master to read, fails with the FIFO full.
+    pub fn write_byte(&mut self, byte: u8) -> Result<(), ()> {
+        unsafe {
+            if bsc_slave().FR.get(FR_TXFF) {
+                Err(())
+            } else {
+                bsc_slave().DR.write(byte as u32);
+                Ok(())
+            }
+        }
+    }
+
+    /// Raises `Irq::BSC_SLAVE` on received data, on the TX FIFO running
+    /// low and on errors, as selected.
+    pub fn enable_interrupts(&mut self, rx: bool, tx: bool, errors: bool) -> &mut Self {
+        let mut imsc = 0;
+        if rx {
+            imsc |= INT_RX;
+        }
+        if tx {
+            imsc |= INT_TX;
+        }
+        if errors {
+            imsc |= INT_BREAK | INT_OVERRUN;
+        }
+        unsafe {
+            // Interrupt when the RX FIFO is 1/8 full, the TX FIFO 1/8 empty
+            bsc_slave().IFLS.write(0);
+            bsc_slave().IMSC.write(imsc);
+        }
+        self
+    }
+
+    /// Hands received bytes to `handler`, refills the TX FIFO from it and
+    /// reports then clears pending errors.
+    pub fn service<H: Handler>(&mut self, handler: &mut H) {
+        while let Some(byte) = self.read_byte() {
+            handler.on_receive(byte);
+        }
+        while unsafe { !bsc_slave().FR.get(FR_TXFF) } {
+            match handler.on_transmit() {
+                Some(byte) => unsafe { bsc_slave().DR.write(byte as u32) },
+                None => break,
+            }
+        }
+        let errors = self.errors();
+        if errors.any() {
+            handler.on_error(errors);
+            self.clear_errors();
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Data cache maintenance and DMA-coherent buffers.
+//!
+//! The VideoCore and the DMA engines do not snoop the ARM caches. Memory
+//! they read must be cleaned first, memory they write must be invalidated
+//! before the CPU reads it back. Range operations work on virtual
+//! addresses up to the point of coherency, set/way operations walk every
+//! data cache level up to the level of coherency and are only meant for
+//! turning caches on and off.
+
+use core::ops::{Deref, DerefMut};
+
+/// Smallest data cache line of the core, in bytes
+#[inline]
+pub fn line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    4 << ((ctr >> 16) & 0xF)
+}
+
+macro_rules! range_op {
+    ($name:ident, $op:literal) => {
+        pub unsafe fn $name(addr: usize, size: usize) {
+            let line = line_size();
+            let mut a = addr & !(line - 1);
+            let end = addr + size;
+            while a < end {
+                asm!($op, in(reg) a);
+                a += line;
+            }
+            asm!("dsb sy");
+        }
+    };
+}
+
+range_op!(clean_range, "dc cvac, {}");
+range_op!(invalidate_range, "dc ivac, {}");
+range_op!(clean_invalidate_range, "dc civac, {}");
+
+#[derive(Clone, Copy)]
+enum SetWayOp {
+    Clean,
+    Invalidate,
+    CleanInvalidate,
+}
+
+unsafe fn set_way(op: SetWayOp) {
+    let clidr: u64;
+    asm!("mrs {}, clidr_el1", out(reg) clidr);
+    let level_of_coherency = (clidr >> 24) & 0b111;
+
+    for level in 0..level_of_coherency {
+        let cache_type = (clidr >> (3 * level)) & 0b111;
+        // 0: none, 1: instruction only
+        if cache_type < 2 {
+            continue;
+        }
+
+        let ccsidr: u64;
+        asm!(
+            "msr csselr_el1, {}",
+            "isb",
+            "mrs {}, ccsidr_el1",
+            in(reg) level << 1,
+            out(reg) ccsidr,
+        );
+        let line_shift = (ccsidr & 0b111) + 4;
+        let ways = ((ccsidr >> 3) & 0x3FF) + 1;
+        let sets = ((ccsidr >> 13) & 0x7FFF) + 1;
+        let way_shift = if ways > 1 {
+            (ways as u32 - 1).leading_zeros()
+        } else {
+            0
+        };
+
+        for way in 0..ways {
+            for set in 0..sets {
+                let v = (way << way_shift) | (set << line_shift) | (level << 1);
+                match op {
+                    SetWayOp::Clean => asm!("dc csw, {}", in(reg) v),
+                    SetWayOp::Invalidate => asm!("dc isw, {}", in(reg) v),
+                    SetWayOp::CleanInvalidate => asm!("dc cisw, {}", in(reg) v),
+                }
+            }
+        }
+    }
+    asm!("dsb sy", "isb");
+}
+
+pub unsafe fn clean_all() {
+    set_way(SetWayOp::Clean)
+}
+
+/// Drops every dirty line, only safe before the caches are first enabled.
+pub unsafe fn invalidate_all() {
+    set_way(SetWayOp::Invalidate)
+}
+
+pub unsafe fn clean_invalidate_all() {
+    set_way(SetWayOp::CleanInvalidate)
+}
+
+pub unsafe fn invalidate_icache() {
+    asm!("ic iallu", "dsb sy", "isb");
+}
+
+/// Memory shared with the VideoCore or a DMA engine.
+///
+/// The content is aligned to and padded up to 64 bytes, the largest cache
+/// line of the Pi cores, so maintenance never touches neighbouring data.
+/// Call `prepare_for_device` before handing the buffer to a device and
+/// `complete_from_device` once the device is done with it.
+#[repr(C, align(64))]
+pub struct DmaBuffer<T> {
+    data: T,
+}
+
+impl<T> DmaBuffer<T> {
+    pub const fn new(data: T) -> Self {
+        DmaBuffer { data }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *const T {
+        &self.data
+    }
+
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        &mut self.data
+    }
+
+    /// Address of the buffer for VideoCore bus masters (legacy DMA,
+    /// mailbox framebuffer), through the uncached `0xC000_0000` alias.
+    #[inline]
+    pub fn bus_address(&self) -> u32 {
+        (self.as_ptr() as usize as u32 & 0x3FFF_FFFF) | 0xC000_0000
+    }
+
+    /// Writes CPU changes back to memory so that the device sees them,
+    /// and evicts the lines so that no stale copy survives the transfer.
+    pub fn prepare_for_device(&mut self) {
+        unsafe { clean_invalidate_range(self.as_ptr() as usize, core::mem::size_of::<Self>()) };
+    }
+
+    /// Discards lines the CPU may have speculatively fetched while the
+    /// device was writing.
+    pub fn complete_from_device(&mut self) {
+        unsafe { invalidate_range(self.as_ptr() as usize, core::mem::size_of::<Self>()) };
+    }
+}
+
+impl<T> Deref for DmaBuffer<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for DmaBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
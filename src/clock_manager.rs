@@ -0,0 +1,274 @@
+//! Clock manager: the clock generators feeding the PWM, PCM and general
+//! purpose clock outputs.
+//!
+//! Every register write must carry `PASSWORD` in its top byte. A
+//! generator must be stopped, and no longer `BUSY`, before its source or
+//! divider change.
+//!
+//! The divider has a 12-bit integer and a 12-bit fractional part. Without
+//! MASH only the integer part is used; the MASH noise shaper dithers
+//! between neighbouring integer dividers to average the fraction, at the
+//! cost of jitter and of a higher minimum integer divider per stage.
+
+use register::*;
+
+use super::{
+    board::{self, Board},
+    gpio::{Function, GPIO},
+};
+
+const CM_OFFSET: usize = 0x10_1000;
+
+pub const PASSWORD: u32 = 0x5A << 24;
+
+#[inline(always)]
+pub fn cm_base() -> usize {
+    super::peripherals_base() + CM_OFFSET
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct ClockStruct {
+    pub CTL: Register<u32>,
+    pub DIV: Register<u32>,
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct CMStruct {
+    _reserved0: [u32; 28],
+    pub GP0: ClockStruct,
+    pub GP1: ClockStruct,
+    pub GP2: ClockStruct,
+    _reserved1: [u32; 4],
+    pub PCM: ClockStruct,
+    pub PWM: ClockStruct,
+}
+
+pub unsafe fn cm<'a>() -> &'a mut CMStruct {
+    &mut *(cm_base() as *mut CMStruct)
+}
+
+// CTL
+const CTL_SRC: u32 = 0;
+const CTL_ENAB: u32 = 1 << 4;
+const CTL_KILL: u32 = 1 << 5;
+const CTL_BUSY: u32 = 7;
+const CTL_MASH: u32 = 9;
+
+// DIV
+const DIV_DIVI: u32 = 12;
+const MAX_DIVI: u32 = 0xFFF;
+const MAX_DIVF: u32 = 0xFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generator {
+    Gp0,
+    Gp1,
+    Gp2,
+    Pcm,
+    Pwm,
+}
+
+impl Generator {
+    pub unsafe fn regs<'a>(self) -> &'a mut ClockStruct {
+        let cm = cm();
+        match self {
+            Generator::Gp0 => &mut cm.GP0,
+            Generator::Gp1 => &mut cm.GP1,
+            Generator::Gp2 => &mut cm.GP2,
+            Generator::Pcm => &mut cm.PCM,
+            Generator::Pwm => &mut cm.PWM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mash {
+    /// Integer division only
+    Integer = 0,
+    Stage1 = 1,
+    Stage2 = 2,
+    Stage3 = 3,
+}
+
+impl Mash {
+    /// Smallest integer divider the filter works with
+    pub fn min_divi(self) -> u32 {
+        match self {
+            Mash::Integer => 1,
+            Mash::Stage1 => 2,
+            Mash::Stage2 => 3,
+            Mash::Stage3 => 5,
+        }
+    }
+}
+
+/// Value of a `DIV` register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divider {
+    pub divi: u32,
+    /// In 1/4096ths
+    pub divf: u32,
+}
+
+impl Divider {
+    /// Divider bringing `source_hz` closest to `hz`, within what `mash`
+    /// allows. Fails if `hz` cannot be reached.
+    pub fn for_frequency(source_hz: u32, hz: u32, mash: Mash) -> Result<Self, ()> {
+        if hz == 0 {
+            return Err(());
+        }
+        // In 1/4096ths, rounded to nearest
+        let scaled = ((source_hz as u64) << 12) + hz as u64 / 2;
+        let scaled = scaled / hz as u64;
+        let (divi, divf) = match mash {
+            Mash::Integer => (((scaled + 0x800) >> 12) as u32, 0),
+            _ => ((scaled >> 12) as u32, (scaled & MAX_DIVF as u64) as u32),
+        };
+        if divi < mash.min_divi() || divi > MAX_DIVI {
+            return Err(());
+        }
+        Ok(Divider { divi, divf })
+    }
+
+    /// Average output rate out of `source_hz`, with the fields clamped
+    /// the way `start` programs them
+    pub fn frequency(self, source_hz: u32) -> u32 {
+        let divi = self.divi.max(1).min(MAX_DIVI) as u64;
+        let divf = (self.divf & MAX_DIVF) as u64;
+        (((source_hz as u64) << 12) / (divi << 12 | divf)) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Ground = 0,
+    Oscillator = 1,
+    TestDebug0 = 2,
+    TestDebug1 = 3,
+    PllA = 4,
+    PllC = 5,
+    PllD = 6,
+    HdmiAux = 7,
+}
+
+impl Source {
+    /// Rate of the sources with a fixed frequency, in Hz. The others
+    /// depend on the firmware configuration.
+    pub fn frequency(self) -> Option<u32> {
+        match (self, board::current()) {
+            (Source::Oscillator, Board::RaspberryPi3) => Some(19_200_000),
+            (Source::Oscillator, Board::RaspberryPi4) => Some(54_000_000),
+            (Source::PllD, Board::RaspberryPi3) => Some(500_000_000),
+            (Source::PllD, Board::RaspberryPi4) => Some(750_000_000),
+            _ => None,
+        }
+    }
+}
+
+/// Stops `clock` and waits until it actually is.
+pub unsafe fn stop(clock: &mut ClockStruct) {
+    let ctl = clock.CTL.read() & 0xFF & !CTL_ENAB;
+    clock.CTL.write(PASSWORD | ctl);
+    while clock.CTL.get(CTL_BUSY) {}
+}
+
+/// Stops `clock` at once, glitching its output.
+pub unsafe fn kill(clock: &mut ClockStruct) {
+    clock.CTL.write(PASSWORD | CTL_KILL);
+    while clock.CTL.get(CTL_BUSY) {}
+    clock.CTL.write(PASSWORD);
+}
+
+/// Restarts `clock` from `source` through `divider` and `mash`. The source
+/// and MASH are set with the clock stopped, then enabled on their own.
+pub unsafe fn start(clock: &mut ClockStruct, source: Source, divider: Divider, mash: Mash) {
+    stop(clock);
+    let divi = divider.divi.max(1).min(MAX_DIVI);
+    clock
+        .DIV
+        .write(PASSWORD | divi << DIV_DIVI | divider.divf & MAX_DIVF);
+    let ctl = (source as u32) << CTL_SRC | (mash as u32) << CTL_MASH;
+    clock.CTL.write(PASSWORD | ctl);
+    clock.CTL.write(PASSWORD | ctl | CTL_ENAB);
+}
+
+/// Restarts `clock` from `source` divided by the integer `divi`, clamped
+/// to 1–4095.
+pub unsafe fn start_integer(clock: &mut ClockStruct, source: Source, divi: u32) {
+    start(clock, source, Divider { divi, divf: 0 }, Mash::Integer);
+}
+
+pub unsafe fn is_busy(clock: &ClockStruct) -> bool {
+    clock.CTL.get(CTL_BUSY)
+}
+
+/// General purpose clock output
+pub struct Gpclk {
+    generator: Generator,
+    frequency: u32,
+}
+
+impl Gpclk {
+    /// GPCLK0 to GPCLK2. Only one `Gpclk` must exist per clock. GPCLK1 is
+    /// used by the firmware on some boards.
+    pub unsafe fn new(index: usize) -> Result<Self, ()> {
+        let generator = match index {
+            0 => Generator::Gp0,
+            1 => Generator::Gp1,
+            2 => Generator::Gp2,
+            _ => return Err(()),
+        };
+        Ok(Gpclk {
+            generator,
+            frequency: 0,
+        })
+    }
+
+    fn pin(&self) -> usize {
+        match self.generator {
+            Generator::Gp0 => 4,
+            Generator::Gp1 => 5,
+            _ => 6,
+        }
+    }
+
+    /// Routes the clock to GPIO 4, 5 or 6.
+    pub unsafe fn init_pin(&mut self) -> &mut Self {
+        GPIO(self.pin()).set_function(Function::Alternate0);
+        self
+    }
+
+    /// Starts the clock at the rate closest to `hz`, returns it. Fails for
+    /// a source of unknown frequency or an unreachable rate.
+    pub unsafe fn start(&mut self, source: Source, hz: u32, mash: Mash) -> Result<u32, ()> {
+        let source_hz = source.frequency().ok_or(())?;
+        let divider = Divider::for_frequency(source_hz, hz, mash)?;
+        start(self.generator.regs(), source, divider, mash);
+        self.frequency = divider.frequency(source_hz);
+        Ok(self.frequency)
+    }
+
+    /// Starts the clock with a raw divider, for sources of unknown
+    /// frequency.
+    pub unsafe fn start_with(&mut self, source: Source, divider: Divider, mash: Mash) -> &mut Self {
+        start(self.generator.regs(), source, divider, mash);
+        self.frequency = source.frequency().map_or(0, |hz| divider.frequency(hz));
+        self
+    }
+
+    pub fn stop(&mut self) -> &mut Self {
+        unsafe { stop(self.generator.regs()) };
+        self
+    }
+
+    /// Last rate set, 0 if unknown
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn is_busy(&self) -> bool {
+        unsafe { is_busy(self.generator.regs()) }
+    }
+}
@@ -0,0 +1,175 @@
+//! Secondary core bring-up.
+//!
+//! The firmware holds cores 1–3 in a `wfe` loop polling their spin table
+//! slot (0xE0, 0xE8 and 0xF0). `start_core` points the slot to a small
+//! trampoline that loads the requested stack, drops to EL1, turns the MMU
+//! and caches on with the tables of `mmu::init` and calls the entry
+//! function with its argument. Under the `rt` feature, cores that
+//! entered `_start` themselves wait on the same slots.
+
+use core::ptr::{read_volatile, write_volatile};
+
+pub const CORES: usize = 4;
+
+const SPIN_TABLE: usize = 0xD8;
+
+global_asm!(
+    r#"
+.section .text.hal_secondary_entry, "ax"
+.global __hal_secondary_entry
+__hal_secondary_entry:
+    mrs x0, mpidr_el1
+    and x0, x0, #3
+    ldr x1, =__hal_cpu_boot
+    mov x2, #24
+    madd x1, x0, x2, x1
+    ldr x2, [x1]
+    mov sp, x2
+    b __hal_secondary_start
+"#
+);
+
+extern "C" {
+    fn __hal_secondary_entry();
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct BootInfo {
+    stack: usize,
+    entry: usize,
+    arg: usize,
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+static mut __hal_cpu_boot: [BootInfo; CORES] = [BootInfo {
+    stack: 0,
+    entry: 0,
+    arg: 0,
+}; CORES];
+
+#[no_mangle]
+unsafe extern "C" fn __hal_secondary_start(core: usize) -> ! {
+    let boot = read_volatile(&__hal_cpu_boot[core]);
+    super::el::enter_el1(secondary_el1, boot.stack, core)
+}
+
+/// Joins the translation tables of the primary core, so that the locks of
+/// `sync` work here too, then runs the requested entry.
+extern "C" fn secondary_el1(core: usize) -> ! {
+    unsafe {
+        super::mmu::enable_secondary();
+        let boot = read_volatile(&__hal_cpu_boot[core]);
+        let entry: extern "C" fn(usize) -> ! = core::mem::transmute(boot.entry);
+        entry(boot.arg)
+    }
+}
+
+/// Stack memory for a secondary core.
+#[repr(C, align(16))]
+pub struct Stack<const N: usize>([u8; N]);
+impl<const N: usize> Stack<N> {
+    pub const fn new() -> Self {
+        Stack([0; N])
+    }
+    pub fn top(&mut self) -> usize {
+        self.0.as_mut_ptr() as usize + N
+    }
+}
+
+/// Top of the stack `link.ld` reserves for `core`.
+#[cfg(feature = "rt")]
+pub fn default_stack(core: usize) -> usize {
+    extern "C" {
+        static __stack_top: u8;
+        static __core_stack_size: u8;
+    }
+    unsafe {
+        let top = &__stack_top as *const u8 as usize;
+        let size = &__core_stack_size as *const u8 as usize;
+        top - core * size
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu(usize);
+impl Cpu {
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.0
+    }
+    #[inline]
+    pub fn is_primary(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[inline(always)]
+pub fn current() -> Cpu {
+    Cpu(super::core_id())
+}
+
+#[inline(always)]
+fn spin_table_slot(core: usize) -> *mut usize {
+    (SPIN_TABLE + 8 * core) as *mut usize
+}
+
+#[inline(always)]
+unsafe fn clean_to_poc(addr: usize) {
+    asm!("dc civac, {}", in(reg) addr);
+}
+
+/// Starts core `id` (1 to 3) running `entry(arg)` at EL1 on `stack`.
+///
+/// The core joins the translation tables of the caller before `entry`
+/// runs. Fails for core 0 and for nonexistent cores, and while the MMU or
+/// the data cache of the caller is off or was not turned on by
+/// `mmu::init`/`mmu::enable`: the locks of `sync` do not
+/// exclude anything then, and the cores would race on the console, the
+/// heap and the peripherals. A core can only be started once, it never
+/// returns to the spin table.
+pub unsafe fn start_core(
+    id: usize,
+    entry: extern "C" fn(usize) -> !,
+    stack: usize,
+    arg: usize,
+) -> Result<(), ()> {
+    if id == 0 || id >= CORES || !super::sync::exclusives_available() || !super::mmu::is_shared() {
+        return Err(());
+    }
+
+    write_volatile(
+        &mut __hal_cpu_boot[id],
+        BootInfo {
+            stack,
+            entry: entry as usize,
+            arg,
+        },
+    );
+    clean_to_poc(&__hal_cpu_boot[id] as *const BootInfo as usize);
+
+    let slot = spin_table_slot(id);
+    write_volatile(
+        slot,
+        __hal_secondary_entry as unsafe extern "C" fn() as usize,
+    );
+    clean_to_poc(slot as usize);
+    asm!("dsb sy", "sev");
+
+    Ok(())
+}
+
+/// Waits on the spin table like the firmware does, then jumps to the
+/// released address. Used by the `rt` startup for secondary cores.
+#[cfg(feature = "rt")]
+pub(crate) unsafe fn wait_for_release() -> ! {
+    let slot = spin_table_slot(super::core_id());
+    loop {
+        let target = read_volatile(slot);
+        if target != 0 {
+            asm!("br {}", in(reg) target, options(noreturn));
+        }
+        asm!("wfe");
+    }
+}
@@ -0,0 +1,510 @@
+//! DMA engines.
+//!
+//! Channels 0 to 14 share one register block, 0x100 bytes apart. Channels
+//! 0–6 are full engines, with 2D mode and 30-bit lengths. "Lite" channels
+//! (7–10 on the BCM2711, reported by `DEBUG.LITE`) have no 2D mode and
+//! move at most 64kB per control block. Channels 11–14 of the BCM2711 are
+//! DMA4 engines with their own control block format and 40-bit addresses.
+//!
+//! The firmware keeps some channels for itself. `Channel::allocate` only
+//! hands out those of the mask it reports, see `available_channels`.
+//!
+//! Legacy engines see memory through the VideoCore bus: SDRAM at
+//! `bus_address`, the first GB only, and peripherals at `0x7E..`
+//! addresses such as `spi::Spi::fifo_bus_address`. DMA4 engines use ARM
+//! physical addresses, with peripherals at `dma4_peripheral_address`.
+//!
+//! Control blocks must be 32-byte aligned and cleaned from the data cache
+//! before the engine reads them, which `start` does for the whole chain.
+//! Data buffers are the caller's to maintain, see `cache::DmaBuffer`.
+//!
+//! The control block encoders live in the `dma-cb` crate, so that their
+//! bit packing is tested on the host.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use register::*;
+
+pub use dma_cb::{
+    bus_address, dma4_peripheral_address, ControlBlock, Direction, Dma4ControlBlock, Dreq,
+};
+
+use super::{
+    board::{self, Board},
+    cache,
+    interrupt::Irq,
+    mailbox, time,
+};
+
+const DMA_OFFSET: usize = 0x7000;
+const CHANNEL_STRIDE: usize = 0x100;
+const CHANNEL_COUNT: usize = 15;
+const FIRST_DMA4_CHANNEL: usize = 11;
+
+const INT_STATUS_OFFSET: usize = 0xFE0;
+const ENABLE_OFFSET: usize = 0xFF0;
+
+#[inline(always)]
+pub fn dma_base() -> usize {
+    super::peripherals_base() + DMA_OFFSET
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct ChannelStruct {
+    pub CS: Register<u32>,
+    pub CONBLK_AD: Register<u32>,
+    pub TI: Register<u32>,
+    pub SOURCE_AD: Register<u32>,
+    pub DEST_AD: Register<u32>,
+    pub TXFR_LEN: Register<u32>,
+    pub STRIDE: Register<u32>,
+    pub NEXTCONBK: Register<u32>,
+    pub DEBUG: Register<u32>,
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct Dma4ChannelStruct {
+    pub CS: Register<u32>,
+    /// Control block address shifted right by 5
+    pub CB: Register<u32>,
+    _reserved: u32,
+    pub DEBUG: Register<u32>,
+    pub TI: Register<u32>,
+    pub SRC: Register<u32>,
+    pub SRCI: Register<u32>,
+    pub DEST: Register<u32>,
+    pub DESTI: Register<u32>,
+    pub LEN: Register<u32>,
+    pub NEXT_CB: Register<u32>,
+    pub DEBUG2: Register<u32>,
+}
+
+unsafe fn int_status<'a>() -> &'a mut Register<u32> {
+    &mut *((dma_base() + INT_STATUS_OFFSET) as *mut Register<u32>)
+}
+
+unsafe fn enable<'a>() -> &'a mut Register<u32> {
+    &mut *((dma_base() + ENABLE_OFFSET) as *mut Register<u32>)
+}
+
+// CS, both engine types
+const CS_ACTIVE: u32 = 1 << 0;
+const CS_END: u32 = 1 << 1;
+const CS_INT: u32 = 1 << 2;
+const CS_DREQ: u32 = 1 << 3;
+const CS_WAIT_FOR_OUTSTANDING_WRITES: u32 = 1 << 28;
+const CS_ABORT: u32 = 1 << 30;
+const CS_RESET: u32 = 1 << 31;
+const CS_PRIORITY: u32 = 16;
+const CS_PANIC_PRIORITY: u32 = 20;
+// CS, legacy engines
+const CS_PAUSED: u32 = 1 << 4;
+const CS_ERROR: u32 = 1 << 8;
+// CS, DMA4 engines
+const CS4_PAUSED: u32 = 0b11 << 4;
+const CS4_ERROR: u32 = 1 << 10;
+const CS4_HALT: u32 = 1 << 31;
+
+// DEBUG, legacy engines
+const DEBUG_READ_LAST_NOT_SET: u32 = 1 << 0;
+const DEBUG_FIFO_ERROR: u32 = 1 << 1;
+const DEBUG_READ_ERROR: u32 = 1 << 2;
+const DEBUG_LITE: u32 = 28;
+// DEBUG, DMA4 engines
+const DEBUG4_WRITE_ERROR: u32 = 1 << 0;
+const DEBUG4_FIFO_ERROR: u32 = 1 << 1;
+const DEBUG4_READ_ERROR: u32 = 1 << 2;
+const DEBUG4_RESET: u32 = 1 << 23;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Full,
+    Lite,
+    /// BCM2711 40-bit engine
+    Dma4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// An AXI read came back with an error
+    Read,
+    /// An AXI write came back with an error, DMA4 only
+    Write,
+    /// The engine's read FIFO went out of sync
+    Fifo,
+    /// A burst ended without its last flag, legacy engines only
+    ReadLastNotSet,
+    /// `Channel::wait` gave up, the chain is still running
+    Timeout,
+}
+
+/// Decoded `CS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub active: bool,
+    /// The last control block of the chain completed
+    pub end: bool,
+    /// A control block with interrupts enabled completed
+    pub interrupt: bool,
+    /// The DREQ of the current control block is asserted
+    pub dreq: bool,
+    pub paused: bool,
+    pub error: bool,
+}
+
+/// Channels the firmware leaves to the ARM, fetched once
+static AVAILABLE: AtomicU32 = AtomicU32::new(0);
+static ALLOCATED: AtomicU32 = AtomicU32::new(0);
+
+/// Bit `n` set when channel `n` may be used, as reported by the firmware.
+/// Fails if the firmware does not answer.
+pub fn available_channels() -> Result<u32, ()> {
+    match AVAILABLE.load(Ordering::Relaxed) {
+        0 => {
+            let mask: u32 = mailbox::Message::new()
+                .with(mailbox::tag::GetDmaChannels)
+                .commit()?;
+            let mask = mask & ((1 << CHANNEL_COUNT) - 1);
+            AVAILABLE.store(mask, Ordering::Relaxed);
+            Ok(mask)
+        }
+        mask => Ok(mask),
+    }
+}
+
+/// Channels with their `INT` flag set, bit `n` for channel `n`
+pub fn pending_interrupts() -> u32 {
+    unsafe { int_status().read() }
+}
+
+fn kind_of(index: usize) -> Kind {
+    if index >= FIRST_DMA4_CHANNEL && board::current() == Board::RaspberryPi4 {
+        Kind::Dma4
+    } else if unsafe { channel_regs(index).DEBUG.get(DEBUG_LITE) } {
+        Kind::Lite
+    } else {
+        Kind::Full
+    }
+}
+
+/// Cleans every block of the chain starting at `first` from the data
+/// cache. `visit` checks a block and gives the next one. A second cursor
+/// follows at half speed, so that a loop anywhere in the chain ends the
+/// walk once it has been gone through entirely.
+unsafe fn clean_chain<B, F>(first: *const B, mut visit: F) -> Result<(), ()>
+where
+    F: FnMut(&B) -> Result<Option<*const B>, ()>,
+{
+    let mut block = first;
+    let mut slow = first;
+    let mut steps = 0usize;
+    loop {
+        cache::clean_range(block as usize, core::mem::size_of::<B>());
+        block = match visit(&*block)? {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        steps += 1;
+        if steps % 2 == 0 {
+            // Behind `block`, so never at the end of the chain
+            slow = visit(&*slow)?.ok_or(())?;
+        }
+        if block == slow {
+            return Ok(());
+        }
+    }
+}
+
+unsafe fn channel_regs<'a>(index: usize) -> &'a mut ChannelStruct {
+    &mut *((dma_base() + index * CHANNEL_STRIDE) as *mut ChannelStruct)
+}
+
+unsafe fn dma4_regs<'a>(index: usize) -> &'a mut Dma4ChannelStruct {
+    &mut *((dma_base() + index * CHANNEL_STRIDE) as *mut Dma4ChannelStruct)
+}
+
+/// An allocated channel, given back on drop
+pub struct Channel {
+    index: usize,
+    kind: Kind,
+}
+
+impl Channel {
+    /// Takes channel `index`, if the firmware leaves it to the ARM and it
+    /// is not taken yet.
+    pub fn take(index: usize) -> Result<Self, ()> {
+        if index >= CHANNEL_COUNT || available_channels()? & (1 << index) == 0 {
+            return Err(());
+        }
+        if ALLOCATED.fetch_or(1 << index, Ordering::Acquire) & (1 << index) != 0 {
+            return Err(());
+        }
+        let mut channel = Channel {
+            index,
+            kind: kind_of(index),
+        };
+        unsafe { enable().set(index as u32, true) };
+        channel.reset();
+        Ok(channel)
+    }
+
+    /// Takes the lowest free channel of `kind`.
+    pub fn allocate(kind: Kind) -> Result<Self, ()> {
+        let free = available_channels()? & !ALLOCATED.load(Ordering::Relaxed);
+        (0..CHANNEL_COUNT)
+            .filter(|&i| free & (1 << i) != 0 && kind_of(i) == kind)
+            .find_map(|i| Channel::take(i).ok())
+            .ok_or(())
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Interrupt raised by control blocks with interrupts enabled.
+    /// Channels 7 and 8, 9 and 10 share theirs.
+    pub fn irq(&self) -> Irq {
+        match self.index {
+            0 => Irq::DMA_0,
+            1 => Irq::DMA_1,
+            2 => Irq::DMA_2,
+            3 => Irq::DMA_3,
+            4 => Irq::DMA_4,
+            5 => Irq::DMA_5,
+            6 => Irq::DMA_6,
+            7 | 8 => Irq::DMA_7_8,
+            9 | 10 => Irq::DMA_9_10,
+            11 => Irq::DMA_11,
+            12 => Irq::DMA_12,
+            13 => Irq::DMA_13,
+            _ => Irq::DMA_14,
+        }
+    }
+
+    #[inline]
+    fn cs(&self) -> &mut Register<u32> {
+        // Same place for both engine types
+        unsafe { &mut channel_regs(self.index).CS }
+    }
+
+    /// Stops the channel and clears its state.
+    pub fn reset(&mut self) -> &mut Self {
+        unsafe {
+            if self.kind == Kind::Dma4 {
+                let regs = dma4_regs(self.index);
+                regs.DEBUG.write(DEBUG4_RESET);
+                regs.CS.write(CS_END | CS_INT);
+            } else {
+                let regs = channel_regs(self.index);
+                regs.CS.write(CS_RESET);
+                regs.CS.write(CS_END | CS_INT);
+                regs.DEBUG
+                    .write(DEBUG_READ_LAST_NOT_SET | DEBUG_FIFO_ERROR | DEBUG_READ_ERROR);
+            }
+        }
+        self
+    }
+
+    /// Bus priority of normal and panicking transfers, 0 to 15.
+    pub fn set_priority(&mut self, priority: u8, panic: u8) -> &mut Self {
+        let mask = 0xFF << CS_PRIORITY;
+        let value =
+            (priority as u32 & 0xF) << CS_PRIORITY | (panic as u32 & 0xF) << CS_PANIC_PRIORITY;
+        unsafe {
+            // ACTIVE is written back as read, so a running chain goes on
+            let cs = self.cs().read() & !(mask | CS_END | CS_INT);
+            self.cs().write(cs | value);
+        }
+        self
+    }
+
+    /// Runs the chain starting at `cb` on a legacy engine, after cleaning
+    /// every block of it from the data cache. Fails on a DMA4 channel,
+    /// with 2D blocks on a lite one, or while the channel is still active.
+    pub fn start(&mut self, cb: &ControlBlock) -> Result<&mut Self, ()> {
+        if self.kind == Kind::Dma4 || self.is_active() {
+            return Err(());
+        }
+        let lite = self.kind == Kind::Lite;
+        let first = bus_address(cb).ok_or(())?;
+        unsafe {
+            clean_chain(cb, |block| {
+                if lite && block.is_2d() {
+                    return Err(());
+                }
+                Ok(match block.next {
+                    0 => None,
+                    next => Some((next & 0x3FFF_FFFF) as usize as *const ControlBlock),
+                })
+            })?
+        };
+
+        let cs = unsafe { self.cs().read() } & (0xFF << CS_PRIORITY);
+        unsafe {
+            let regs = channel_regs(self.index);
+            regs.CS.write(CS_END | CS_INT);
+            regs.CONBLK_AD.write(first);
+            regs.CS
+                .write(cs | CS_WAIT_FOR_OUTSTANDING_WRITES | CS_ACTIVE);
+        }
+        Ok(self)
+    }
+
+    /// Runs the chain starting at `cb` on a DMA4 engine, after cleaning
+    /// every block of it from the data cache. Fails on a legacy channel or
+    /// while the channel is still active.
+    pub fn start_dma4(&mut self, cb: &Dma4ControlBlock) -> Result<&mut Self, ()> {
+        if self.kind != Kind::Dma4 || self.is_active() {
+            return Err(());
+        }
+        let first = (cb as *const _ as u64 >> 5) as u32;
+        unsafe {
+            clean_chain(cb, |block| {
+                Ok(match block.next_cb {
+                    0 => None,
+                    next => Some(((next as u64) << 5) as usize as *const Dma4ControlBlock),
+                })
+            })?
+        };
+
+        let cs = unsafe { self.cs().read() } & (0xFF << CS_PRIORITY);
+        unsafe {
+            let regs = dma4_regs(self.index);
+            regs.CS.write(CS_END | CS_INT);
+            regs.CB.write(first);
+            regs.CS
+                .write(cs | CS_WAIT_FOR_OUTSTANDING_WRITES | CS_ACTIVE);
+        }
+        Ok(self)
+    }
+
+    pub fn status(&self) -> Status {
+        let cs = unsafe { self.cs().read() };
+        let (paused, error) = match self.kind {
+            Kind::Dma4 => (cs & CS4_PAUSED != 0, cs & CS4_ERROR != 0),
+            _ => (cs & CS_PAUSED != 0, cs & CS_ERROR != 0),
+        };
+        Status {
+            active: cs & CS_ACTIVE != 0,
+            end: cs & CS_END != 0,
+            interrupt: cs & CS_INT != 0,
+            dreq: cs & CS_DREQ != 0,
+            paused,
+            error,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        unsafe { self.cs().read() & CS_ACTIVE != 0 }
+    }
+
+    /// The error that stopped the channel, if any
+    pub fn error(&self) -> Option<Error> {
+        let debug = unsafe {
+            match self.kind {
+                Kind::Dma4 => dma4_regs(self.index).DEBUG.read(),
+                _ => channel_regs(self.index).DEBUG.read(),
+            }
+        };
+        match self.kind {
+            Kind::Dma4 if debug & DEBUG4_READ_ERROR != 0 => Some(Error::Read),
+            Kind::Dma4 if debug & DEBUG4_WRITE_ERROR != 0 => Some(Error::Write),
+            Kind::Dma4 if debug & DEBUG4_FIFO_ERROR != 0 => Some(Error::Fifo),
+            Kind::Dma4 => None,
+            _ if debug & DEBUG_READ_ERROR != 0 => Some(Error::Read),
+            _ if debug & DEBUG_FIFO_ERROR != 0 => Some(Error::Fifo),
+            _ if debug & DEBUG_READ_LAST_NOT_SET != 0 => Some(Error::ReadLastNotSet),
+            _ => None,
+        }
+    }
+
+    /// Acknowledges the channel interrupt, from its handler.
+    pub fn clear_interrupt(&mut self) -> &mut Self {
+        unsafe {
+            let cs = self.cs().read() & !CS_END;
+            self.cs().write(cs | CS_INT);
+        }
+        self
+    }
+
+    /// Outcome of the chain, `None` while it still runs.
+    pub fn poll(&self) -> Option<Result<(), Error>> {
+        let status = self.status();
+        if status.error {
+            Some(Err(self.error().unwrap_or(Error::Read)))
+        } else if status.active {
+            None
+        } else {
+            Some(Ok(()))
+        }
+    }
+
+    /// Waits for the chain to complete or fail, for at most `timeout_us`.
+    /// A chain paced by the DREQ of a stopped peripheral never completes:
+    /// on `Error::Timeout` the channel is left running, `abort` or `reset`
+    /// it.
+    pub fn wait(&mut self, timeout_us: u64) -> Result<(), Error> {
+        let timeout = timeout_us.saturating_mul(time::frequency()) / 1_000_000;
+        let start = time::ticks();
+        loop {
+            if let Some(result) = self.poll() {
+                return result;
+            }
+            if time::ticks().wrapping_sub(start) > timeout {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    pub fn pause(&mut self) -> &mut Self {
+        unsafe {
+            let cs = self.cs().read() & !(CS_ACTIVE | CS_END | CS_INT);
+            self.cs().write(cs);
+        }
+        self
+    }
+
+    pub fn resume(&mut self) -> &mut Self {
+        unsafe {
+            let cs = self.cs().read() & !(CS_END | CS_INT);
+            self.cs().write(cs | CS_ACTIVE);
+        }
+        self
+    }
+
+    /// Drops the current control block and moves to the next one of the
+    /// chain, if any.
+    pub fn abort(&mut self) -> &mut Self {
+        unsafe {
+            let cs = self.cs().read() & !(CS_END | CS_INT);
+            self.cs().write(cs | CS_ABORT);
+        }
+        self
+    }
+
+    /// Stops a DMA4 engine for good once its outstanding transactions
+    /// complete, `reset` makes it usable again. Does nothing on legacy
+    /// engines.
+    pub fn halt(&mut self) -> &mut Self {
+        if self.kind == Kind::Dma4 {
+            unsafe {
+                let cs = self.cs().read() & !(CS_END | CS_INT);
+                self.cs().write(cs | CS4_HALT);
+            }
+        }
+        self
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.reset();
+        ALLOCATED.fetch_and(!(1 << self.index), Ordering::Release);
+    }
+}
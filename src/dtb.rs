@@ -0,0 +1,40 @@
+//! Device tree passed by the firmware.
+//!
+//! `hal::init_from_dtb` records the blob; the helpers below read from it.
+//! Parsing itself lives in the `fdt` crate, re-exported here.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use fdt::{Error, Fdt, Node, Property, Region};
+
+/// Bus address of the main peripheral window, as seen by the VideoCore
+const PERIPHERALS_BUS_ADDRESS: u64 = 0x7E00_0000;
+
+static DTB: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn set(fdt: &Fdt<'static>) {
+    DTB.store(fdt.as_ptr() as usize, Ordering::Relaxed)
+}
+
+/// The blob given to `init_from_dtb`, if any
+pub fn get() -> Option<Fdt<'static>> {
+    match DTB.load(Ordering::Relaxed) {
+        0 => None,
+        ptr => unsafe { Fdt::from_ptr(ptr as *const u8) }.ok(),
+    }
+}
+
+pub fn bootargs() -> Option<&'static str> {
+    get()?.bootargs()
+}
+
+pub fn model() -> Option<&'static str> {
+    get()?.model()
+}
+
+/// ARM address of the peripherals, through the `ranges` of `/soc`
+pub fn peripherals_base(fdt: &Fdt) -> Option<usize> {
+    fdt.find_node("/soc")?
+        .translate(PERIPHERALS_BUS_ADDRESS)
+        .map(|a| a as usize)
+}
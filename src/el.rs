@@ -0,0 +1,107 @@
+//! Exception level transitions.
+//!
+//! Depending on `armstub` and `kernel_old`, the firmware starts the kernel
+//! at EL3 or EL2. `enter_el1` configures whatever lies above EL1 and
+//! `eret`s to EL1h, so the code after it always starts from the same state:
+//! EL1, AArch64, MMU and caches off, FP/SIMD enabled, physical timer and
+//! counter accessible, DAIF masked.
+
+/// SPSR value for EL1h with D, A, I and F masked
+const SPSR_EL1H: u64 = 0b1111 << 6 | 0b0101;
+
+/// HCR_EL2.RW: EL1 is AArch64
+const HCR_RW: u64 = 1 << 31;
+
+/// SCR_EL3: NS, RES1, SMD, HCE, RW
+const SCR_VALUE: u64 = 1 | 0b11 << 4 | 1 << 7 | 1 << 8 | 1 << 10;
+
+/// SCTLR_EL1 RES1 bits, everything else (MMU, caches, alignment) off
+const SCTLR_EL1_RES1: u64 = 0x30D0_0800;
+
+/// CPTR_EL2 RES1 bits, no trap on FP/SIMD
+const CPTR_EL2_RES1: u64 = 0x33FF;
+
+/// CPACR_EL1.FPEN: no trap on FP/SIMD at EL0/EL1
+const CPACR_FPEN: u64 = 0b11 << 20;
+
+/// CNTHCTL_EL2: EL1PCTEN, EL1PCEN
+const CNTHCTL_EL1_ACCESS: u64 = 0b11;
+
+/// Lets EL1 run AArch64 with the physical timer, counter and FP/SIMD,
+/// and presents it the real MIDR/MPIDR. Must run at EL2 or EL3.
+pub unsafe fn configure_el2() {
+    let cnthctl: u64;
+    asm!("mrs {}, cnthctl_el2", out(reg) cnthctl);
+    asm!(
+        "msr cnthctl_el2, {}",
+        "msr cntvoff_el2, xzr",
+        in(reg) cnthctl | CNTHCTL_EL1_ACCESS,
+    );
+
+    asm!(
+        "mrs {0}, midr_el1",
+        "msr vpidr_el2, {0}",
+        "mrs {0}, mpidr_el1",
+        "msr vmpidr_el2, {0}",
+        out(reg) _,
+    );
+
+    asm!("msr hcr_el2, {}", in(reg) HCR_RW);
+    asm!("msr cptr_el2, {}", in(reg) CPTR_EL2_RES1);
+    asm!("msr hstr_el2, xzr");
+}
+
+/// Makes EL2 and below non-secure and AArch64. Must run at EL3.
+pub unsafe fn configure_el3() {
+    asm!("msr scr_el3, {}", in(reg) SCR_VALUE);
+}
+
+unsafe fn configure_el1(stack: usize) {
+    asm!("msr sctlr_el1, {}", in(reg) SCTLR_EL1_RES1);
+    asm!("msr cpacr_el1, {}", in(reg) CPACR_FPEN);
+    asm!("msr sp_el1, {}", in(reg) stack);
+}
+
+/// Switches to EL1h and calls `entry(arg)` on `stack`.
+///
+/// From EL1 this only switches stacks. IRQs, FIQs and SErrors are masked
+/// on arrival; install a vector table before unmasking them.
+pub unsafe fn enter_el1(entry: extern "C" fn(usize) -> !, stack: usize, arg: usize) -> ! {
+    match super::execption_level() {
+        3 => {
+            configure_el3();
+            configure_el2();
+            configure_el1(stack);
+            asm!(
+                "msr spsr_el3, {spsr}",
+                "msr elr_el3, {entry}",
+                "eret",
+                spsr = in(reg) SPSR_EL1H,
+                entry = in(reg) entry,
+                in("x0") arg,
+                options(noreturn),
+            )
+        }
+        2 => {
+            configure_el2();
+            configure_el1(stack);
+            asm!(
+                "msr spsr_el2, {spsr}",
+                "msr elr_el2, {entry}",
+                "eret",
+                spsr = in(reg) SPSR_EL1H,
+                entry = in(reg) entry,
+                in("x0") arg,
+                options(noreturn),
+            )
+        }
+        _ => asm!(
+            "mov sp, {stack}",
+            "br {entry}",
+            stack = in(reg) stack,
+            entry = in(reg) entry,
+            in("x0") arg,
+            options(noreturn),
+        ),
+    }
+}
@@ -0,0 +1,296 @@
+//! Exception vector tables and fault reporting.
+//!
+//! One table is provided per exception level. `init` installs the one
+//! matching the level we run at. Every entry saves the full register file
+//! in an [`ExceptionContext`] on the current stack. IRQs are forwarded to
+//! the handlers registered with `interrupt::register`. Synchronous
+//! exceptions and SErrors are decoded, printed and turned into a panic.
+
+use core::fmt;
+
+global_asm!(
+    r#"
+.macro HAL_VECTOR el, kind
+    .balign 0x80
+    sub sp, sp, #288
+    stp x0, x1, [sp, #0]
+    mov x1, #\kind
+    b __hal_exception_common_\el
+.endm
+
+.macro HAL_VECTORS el
+    .section .text.hal_vectors_\el, "ax"
+    .balign 0x800
+    .global __hal_vectors_\el
+__hal_vectors_\el:
+    HAL_VECTOR \el, 0
+    HAL_VECTOR \el, 1
+    HAL_VECTOR \el, 2
+    HAL_VECTOR \el, 3
+    HAL_VECTOR \el, 4
+    HAL_VECTOR \el, 5
+    HAL_VECTOR \el, 6
+    HAL_VECTOR \el, 7
+    HAL_VECTOR \el, 8
+    HAL_VECTOR \el, 9
+    HAL_VECTOR \el, 10
+    HAL_VECTOR \el, 11
+    HAL_VECTOR \el, 12
+    HAL_VECTOR \el, 13
+    HAL_VECTOR \el, 14
+    HAL_VECTOR \el, 15
+
+__hal_exception_common_\el:
+    stp x2, x3, [sp, #16]
+    stp x4, x5, [sp, #32]
+    stp x6, x7, [sp, #48]
+    stp x8, x9, [sp, #64]
+    stp x10, x11, [sp, #80]
+    stp x12, x13, [sp, #96]
+    stp x14, x15, [sp, #112]
+    stp x16, x17, [sp, #128]
+    stp x18, x19, [sp, #144]
+    stp x20, x21, [sp, #160]
+    stp x22, x23, [sp, #176]
+    stp x24, x25, [sp, #192]
+    stp x26, x27, [sp, #208]
+    stp x28, x29, [sp, #224]
+    mrs x9, elr_\el
+    stp x30, x9, [sp, #240]
+    mrs x10, spsr_\el
+    mrs x9, esr_\el
+    stp x10, x9, [sp, #256]
+    mrs x9, far_\el
+    str x9, [sp, #272]
+
+    mov x0, sp
+    bl __hal_handle_exception
+
+    ldp x9, x10, [sp, #248]
+    msr elr_\el, x9
+    msr spsr_\el, x10
+    ldp x0, x1, [sp, #0]
+    ldp x2, x3, [sp, #16]
+    ldp x4, x5, [sp, #32]
+    ldp x6, x7, [sp, #48]
+    ldp x8, x9, [sp, #64]
+    ldp x10, x11, [sp, #80]
+    ldp x12, x13, [sp, #96]
+    ldp x14, x15, [sp, #112]
+    ldp x16, x17, [sp, #128]
+    ldp x18, x19, [sp, #144]
+    ldp x20, x21, [sp, #160]
+    ldp x22, x23, [sp, #176]
+    ldp x24, x25, [sp, #192]
+    ldp x26, x27, [sp, #208]
+    ldp x28, x29, [sp, #224]
+    ldr x30, [sp, #240]
+    add sp, sp, #288
+    eret
+.endm
+
+HAL_VECTORS el1
+HAL_VECTORS el2
+HAL_VECTORS el3
+"#
+);
+
+extern "C" {
+    static __hal_vectors_el1: u8;
+    static __hal_vectors_el2: u8;
+    static __hal_vectors_el3: u8;
+}
+
+/// Registers saved on exception entry. Changes to `gpr`, `elr` and `spsr`
+/// are applied when returning from the exception.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ExceptionContext {
+    /// x0 to x30
+    pub gpr: [u64; 31],
+    pub elr: u64,
+    pub spsr: u64,
+    pub esr: u64,
+    pub far: u64,
+    _padding: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    CurrentElSp0,
+    CurrentElSpx,
+    LowerElAArch64,
+    LowerElAArch32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Synchronous,
+    Irq,
+    Fiq,
+    SError,
+}
+
+/// Installs the vector table of the current exception level.
+pub unsafe fn init() {
+    match super::execption_level() {
+        1 => asm!("msr vbar_el1, {}", "isb", in(reg) &__hal_vectors_el1 as *const u8),
+        2 => asm!("msr vbar_el2, {}", "isb", in(reg) &__hal_vectors_el2 as *const u8),
+        _ => asm!("msr vbar_el3, {}", "isb", in(reg) &__hal_vectors_el3 as *const u8),
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn __hal_handle_exception(ctx: &mut ExceptionContext, kind: u64) {
+    let source = match kind >> 2 {
+        0 => Source::CurrentElSp0,
+        1 => Source::CurrentElSpx,
+        2 => Source::LowerElAArch64,
+        _ => Source::LowerElAArch32,
+    };
+    let kind = match kind & 0b11 {
+        0 => Kind::Synchronous,
+        1 => Kind::Irq,
+        2 => Kind::Fiq,
+        _ => Kind::SError,
+    };
+
+    match kind {
+        Kind::Irq => super::interrupt::handle_irq(ctx),
+        _ => {
+            // The faulting code may have been printing
+            super::uart::force_unlock_console();
+            crate::eprintln!(
+                "\r\nUnhandled {:?} exception from {:?} at EL{}",
+                kind,
+                source,
+                super::execption_level()
+            );
+            crate::eprintln!("{}", Fault::from_context(ctx));
+            crate::eprintln!("ELR = {:#018x}  SPSR = {:#010x}", ctx.elr, ctx.spsr);
+            for (i, pair) in ctx.gpr.chunks(2).enumerate() {
+                match pair {
+                    [a, b] => {
+                        crate::eprintln!(
+                            "x{:<2} = {:#018x}  x{:<2} = {:#018x}",
+                            2 * i,
+                            a,
+                            2 * i + 1,
+                            b
+                        );
+                    }
+                    [a] => {
+                        crate::eprintln!("x{:<2} = {:#018x}", 2 * i, a);
+                    }
+                    _ => {}
+                }
+            }
+            panic!("unhandled {:?} exception", kind);
+        }
+    }
+}
+
+/// Decoded exception syndrome (ESR) with its fault address (FAR).
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub esr: u64,
+    pub far: u64,
+}
+
+impl Fault {
+    pub fn from_context(ctx: &ExceptionContext) -> Self {
+        Fault {
+            esr: ctx.esr,
+            far: ctx.far,
+        }
+    }
+
+    /// Exception class
+    pub fn class(&self) -> u8 {
+        ((self.esr >> 26) & 0x3F) as u8
+    }
+
+    /// Instruction specific syndrome
+    pub fn iss(&self) -> u32 {
+        (self.esr & 0x1FF_FFFF) as u32
+    }
+
+    fn class_name(&self) -> &'static str {
+        match self.class() {
+            0x00 => "Unknown reason",
+            0x01 => "Trapped WFI/WFE",
+            0x07 => "Trapped SIMD/FP access",
+            0x0E => "Illegal execution state",
+            0x11 | 0x15 => "SVC",
+            0x12 | 0x16 => "HVC",
+            0x13 | 0x17 => "SMC",
+            0x18 => "Trapped MSR/MRS/system instruction",
+            0x20 => "Instruction abort from a lower EL",
+            0x21 => "Instruction abort",
+            0x22 => "PC alignment fault",
+            0x24 => "Data abort from a lower EL",
+            0x25 => "Data abort",
+            0x26 => "SP alignment fault",
+            0x28 | 0x2C => "Floating point exception",
+            0x2F => "SError",
+            0x30 | 0x31 => "Breakpoint",
+            0x32 | 0x33 => "Software step",
+            0x34 | 0x35 => "Watchpoint",
+            0x38 | 0x3C => "BRK instruction",
+            _ => "Reserved exception class",
+        }
+    }
+
+    fn is_abort(&self) -> bool {
+        matches!(self.class(), 0x20 | 0x21 | 0x24 | 0x25)
+    }
+
+    fn is_data_abort(&self) -> bool {
+        matches!(self.class(), 0x24 | 0x25)
+    }
+
+    fn fault_status_name(&self) -> &'static str {
+        match self.iss() & 0x3F {
+            0b000000..=0b000011 => "address size fault",
+            0b000100..=0b000111 => "translation fault",
+            0b001001..=0b001011 => "access flag fault",
+            0b001101..=0b001111 => "permission fault",
+            0b010000 => "synchronous external abort",
+            0b010100..=0b010111 => "synchronous external abort on table walk",
+            0b011000 => "synchronous parity or ECC error",
+            0b100001 => "alignment fault",
+            0b110000 => "TLB conflict abort",
+            _ => "unknown fault",
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (EC = {:#04x}, ISS = {:#09x})",
+            self.class_name(),
+            self.class(),
+            self.iss()
+        )?;
+        if self.is_abort() {
+            let status = self.iss() & 0x3F;
+            write!(f, ": {}", self.fault_status_name())?;
+            if status < 0b010000 {
+                write!(f, " at level {}", status & 0b11)?;
+            }
+            if self.is_data_abort() {
+                let write = (self.iss() >> 6) & 1 != 0;
+                write!(f, " on {}", if write { "write" } else { "read" })?;
+            }
+            // FnV: FAR is not valid
+            if (self.iss() >> 10) & 1 == 0 {
+                write!(f, " at {:#018x}", self.far)?;
+            }
+        } else if matches!(self.class(), 0x22 | 0x34 | 0x35) {
+            write!(f, " at {:#018x}", self.far)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use register::*;
+
+const GIC_OFFSET: usize = 0x184_0000;
+const GICD_OFFSET: usize = 0x1000;
+const GICC_OFFSET: usize = 0x2000;
+
+#[inline(always)]
+pub fn gic_base() -> usize {
+    super::peripherals_base() + GIC_OFFSET
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct GICDStruct {
+    pub CTLR: Register<u32>,
+    pub TYPER: Register<u32>,
+    pub IIDR: Register<u32>,
+    _reserved0: [u32; 29],
+    pub IGROUPR: [Register<u32>; 32],
+    pub ISENABLER: [Register<u32>; 32],
+    pub ICENABLER: [Register<u32>; 32],
+    pub ISPENDR: [Register<u32>; 32],
+    pub ICPENDR: [Register<u32>; 32],
+    pub ISACTIVER: [Register<u32>; 32],
+    pub ICACTIVER: [Register<u32>; 32],
+    pub IPRIORITYR: [Register<u32>; 256],
+    pub ITARGETSR: [Register<u32>; 256],
+    pub ICFGR: [Register<u32>; 64],
+    _reserved1: [u32; 128],
+    pub SGIR: Register<u32>,
+}
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct GICCStruct {
+    pub CTLR: Register<u32>,
+    pub PMR: Register<u32>,
+    pub BPR: Register<u32>,
+    pub IAR: Register<u32>,
+    pub EOIR: Register<u32>,
+    pub RPR: Register<u32>,
+    pub HPPIR: Register<u32>,
+    pub ABPR: Register<u32>,
+    pub AIAR: Register<u32>,
+    pub AEOIR: Register<u32>,
+    pub AHPPIR: Register<u32>,
+}
+
+pub unsafe fn gicd<'a>() -> &'a mut GICDStruct {
+    &mut *((gic_base() + GICD_OFFSET) as *mut GICDStruct)
+}
+
+pub unsafe fn gicc<'a>() -> &'a mut GICCStruct {
+    &mut *((gic_base() + GICC_OFFSET) as *mut GICCStruct)
+}
+
+const SPURIOUS: u32 = 1023;
+const DEFAULT_PRIORITY: u8 = 0xA0;
+
+/// A GIC interrupt ID: 0–15 are SGIs, 16–31 PPIs and 32 onwards SPIs.
+///
+/// BCM2711 peripherals are available as associated constants. VideoCore
+/// interrupts start at SPI 64 (ID 96), ARMC interrupts at SPI 32 (ID 64).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqId(pub u32);
+impl IrqId {
+    pub const fn sgi(n: u32) -> Self {
+        IrqId(n)
+    }
+    pub const fn ppi(n: u32) -> Self {
+        IrqId(16 + n)
+    }
+    pub const fn spi(n: u32) -> Self {
+        IrqId(32 + n)
+    }
+    const fn armc(n: u32) -> Self {
+        IrqId(64 + n)
+    }
+    const fn vc(n: u32) -> Self {
+        IrqId(96 + n)
+    }
+
+    pub const HYP_TIMER: Self = Self::ppi(10);
+    pub const VIRTUAL_TIMER: Self = Self::ppi(11);
+    pub const LEGACY_FIQ: Self = Self::ppi(12);
+    pub const SECURE_PHYSICAL_TIMER: Self = Self::ppi(13);
+    pub const PHYSICAL_TIMER: Self = Self::ppi(14);
+    pub const LEGACY_IRQ: Self = Self::ppi(15);
+
+    pub const ARM_TIMER: Self = Self::armc(0);
+    pub const ARM_MAILBOX: Self = Self::armc(1);
+    pub const ARM_DOORBELL_0: Self = Self::armc(2);
+    pub const ARM_DOORBELL_1: Self = Self::armc(3);
+
+    pub const SYSTEM_TIMER_0: Self = Self::vc(0);
+    pub const SYSTEM_TIMER_1: Self = Self::vc(1);
+    pub const SYSTEM_TIMER_2: Self = Self::vc(2);
+    pub const SYSTEM_TIMER_3: Self = Self::vc(3);
+    pub const USB: Self = Self::vc(9);
+    pub const V3D: Self = Self::vc(10);
+    pub const DMA_0: Self = Self::vc(16);
+    pub const DMA_1: Self = Self::vc(17);
+    pub const DMA_2: Self = Self::vc(18);
+    pub const DMA_3: Self = Self::vc(19);
+    pub const DMA_4: Self = Self::vc(20);
+    pub const DMA_5: Self = Self::vc(21);
+    pub const DMA_6: Self = Self::vc(22);
+    pub const DMA_7_8: Self = Self::vc(23);
+    pub const DMA_9_10: Self = Self::vc(24);
+    pub const DMA_11: Self = Self::vc(25);
+    pub const DMA_12: Self = Self::vc(26);
+    pub const DMA_13: Self = Self::vc(27);
+    pub const DMA_14: Self = Self::vc(28);
+    pub const AUX: Self = Self::vc(29);
+    pub const BSC_SLAVE: Self = Self::vc(43);
+    pub const GPIO_BANK_0: Self = Self::vc(49);
+    pub const GPIO_BANK_1: Self = Self::vc(50);
+    pub const GPIO_BANK_2: Self = Self::vc(51);
+    pub const GPIO_ALL: Self = Self::vc(52);
+    pub const I2C: Self = Self::vc(53);
+    pub const SPI: Self = Self::vc(54);
+    pub const PCM: Self = Self::vc(55);
+    pub const SDHOST: Self = Self::vc(56);
+    pub const PL011: Self = Self::vc(57);
+    pub const EMMC2: Self = Self::vc(62);
+
+    #[inline]
+    fn is_spi(self) -> bool {
+        self.0 >= 32
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Trigger {
+    Level = 0b00,
+    Edge = 0b10,
+}
+
+#[derive(Clone, Copy)]
+pub enum SgiTarget {
+    /// Bitmask of cores, bit `n` targets core `n`
+    Cores(u8),
+    AllOthers,
+    Current,
+}
+
+/// Value read from `GICC_IAR`, to be handed back to `end_of_interrupt`.
+#[derive(Debug, Clone, Copy)]
+pub struct Acknowledged(pub(crate) u32);
+impl Acknowledged {
+    pub fn id(&self) -> IrqId {
+        IrqId(self.0 & 0x3FF)
+    }
+    /// Core that raised an SGI, meaningless for other interrupts
+    pub fn source_core(&self) -> usize {
+        ((self.0 >> 10) & 0b111) as usize
+    }
+}
+
+pub struct GIC;
+impl GIC {
+    /// Number of interrupt IDs implemented by the distributor
+    pub fn lines(&self) -> u32 {
+        32 * ((unsafe { gicd().TYPER.read() } & 0x1F) + 1)
+    }
+
+    /// Disables every SPI, gives them the default priority and level
+    /// trigger and routes them to core 0. Only one core should do this.
+    pub unsafe fn init_distributor(&mut self) -> &mut Self {
+        gicd().CTLR.write(0);
+
+        let lines = self.lines() as usize;
+        for i in 1..lines / 32 {
+            gicd().ICENABLER[i].write(0xFFFF_FFFF);
+            gicd().ICPENDR[i].write(0xFFFF_FFFF);
+            gicd().ICACTIVER[i].write(0xFFFF_FFFF);
+        }
+        let priority = u32::from_ne_bytes([DEFAULT_PRIORITY; 4]);
+        for i in 8..lines / 4 {
+            gicd().IPRIORITYR[i].write(priority);
+            gicd().ITARGETSR[i].write(0x0101_0101);
+        }
+        for i in 2..lines / 16 {
+            gicd().ICFGR[i].write(0);
+        }
+
+        gicd().CTLR.write(1);
+        self
+    }
+
+    /// Sets up the banked SGI/PPI state and the CPU interface of the
+    /// calling core. Every core taking interrupts must call this.
+    pub unsafe fn init_cpu_interface(&mut self) -> &mut Self {
+        gicd().ICENABLER[0].write(0xFFFF_FFFF);
+        gicd().ICPENDR[0].write(0xFFFF_FFFF);
+        gicd().ICACTIVER[0].write(0xFFFF_FFFF);
+        let priority = u32::from_ne_bytes([DEFAULT_PRIORITY; 4]);
+        for i in 0..8 {
+            gicd().IPRIORITYR[i].write(priority);
+        }
+
+        gicc().PMR.write(0xFF);
+        gicc().BPR.write(0);
+        gicc().CTLR.write(1);
+        self
+    }
+
+    pub unsafe fn init(&mut self) -> &mut Self {
+        self.init_distributor().init_cpu_interface()
+    }
+
+    pub unsafe fn enable(&mut self, irq: IrqId) -> &mut Self {
+        let i = irq.0 as usize;
+        gicd().ISENABLER[i / 32].write(1 << (i % 32));
+        self
+    }
+
+    pub unsafe fn disable(&mut self, irq: IrqId) -> &mut Self {
+        let i = irq.0 as usize;
+        gicd().ICENABLER[i / 32].write(1 << (i % 32));
+        self
+    }
+
+    pub unsafe fn is_pending(&self, irq: IrqId) -> bool {
+        let i = irq.0 as usize;
+        gicd().ISPENDR[i / 32].get((i % 32) as u32)
+    }
+
+    pub unsafe fn clear_pending(&mut self, irq: IrqId) -> &mut Self {
+        let i = irq.0 as usize;
+        gicd().ICPENDR[i / 32].write(1 << (i % 32));
+        self
+    }
+
+    /// Lower values are higher priorities. The GIC-400 implements the
+    /// upper 4 bits only.
+    pub unsafe fn set_priority(&mut self, irq: IrqId, priority: u8) -> &mut Self {
+        let i = irq.0 as usize;
+        gicd().IPRIORITYR[i / 4].write_with_mask_at(priority as u32, 0xFF, ((i % 4) * 8) as u32);
+        self
+    }
+
+    /// Only meaningful for SPIs, SGIs are always edge-triggered and PPIs
+    /// are fixed by the core.
+    pub unsafe fn set_trigger(&mut self, irq: IrqId, trigger: Trigger) -> &mut Self {
+        let i = irq.0 as usize;
+        gicd().ICFGR[i / 16].write_with_mask_at(trigger as u32, 0b11, ((i % 16) * 2) as u32);
+        self
+    }
+
+    /// Routes an SPI to every core in `cores` (bit `n` for core `n`).
+    pub unsafe fn set_targets(&mut self, irq: IrqId, cores: u8) -> &mut Self {
+        if irq.is_spi() {
+            let i = irq.0 as usize;
+            gicd().ITARGETSR[i / 4].write_with_mask_at(cores as u32, 0xFF, ((i % 4) * 8) as u32);
+        }
+        self
+    }
+
+    #[inline]
+    pub unsafe fn route_to_core(&mut self, irq: IrqId, core: usize) -> &mut Self {
+        self.set_targets(irq, 1 << core)
+    }
+
+    /// Returns `None` on a spurious interrupt, which must not be completed.
+    pub unsafe fn acknowledge(&mut self) -> Option<Acknowledged> {
+        let iar = gicc().IAR.read();
+        if iar & 0x3FF == SPURIOUS {
+            None
+        } else {
+            Some(Acknowledged(iar))
+        }
+    }
+
+    pub unsafe fn end_of_interrupt(&mut self, ack: Acknowledged) -> &mut Self {
+        gicc().EOIR.write(ack.0);
+        self
+    }
+
+    pub unsafe fn send_sgi(&mut self, sgi: u8, target: SgiTarget) -> &mut Self {
+        let (filter, list) = match target {
+            SgiTarget::Cores(cores) => (0b00, cores as u32),
+            SgiTarget::AllOthers => (0b01, 0),
+            SgiTarget::Current => (0b10, 0),
+        };
+        asm!("dsb ishst");
+        gicd()
+            .SGIR
+            .write((filter << 24) | (list << 16) | (sgi as u32 & 0xF));
+        self
+    }
+}
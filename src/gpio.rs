@@ -1,6 +1,18 @@
+use core::fmt::Write;
+
 use register::*;
 
-pub const GPIO_BASE: usize = super::PERIPHERALS_BASE + 0x20_0000;
+use super::{
+    board::{self, Board},
+    time, uart,
+};
+
+const GPIO_OFFSET: usize = 0x20_0000;
+
+#[inline(always)]
+pub fn gpio_base() -> usize {
+    super::peripherals_base() + GPIO_OFFSET
+}
 
 #[repr(packed)]
 pub struct GPIOAccess {
@@ -58,13 +70,126 @@ pub struct GPIOStruct {
     pub GPPUD: PupdnUnion,
 }
 
-#[allow(non_upper_case_globals)]
-const GPIOPtr: *mut GPIOStruct = GPIO_BASE as *mut GPIOStruct;
 unsafe fn gpio<'a>() -> &'a mut GPIOStruct {
-    &mut *GPIOPtr
+    &mut *(gpio_base() as *mut GPIOStruct)
 }
 
-#[derive(Clone, Copy)]
+// In the power management block, written with the PM password
+const PADS_OFFSET: usize = 0x10_002C;
+const PADS_PASSWORD: u32 = 0x5A << 24;
+
+#[inline(always)]
+pub fn pads_base() -> usize {
+    super::peripherals_base() + PADS_OFFSET
+}
+
+/// One register per bank: GPIO 0–27, 28–45 and 46–53
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct PadsStruct {
+    pub PADS: [Register<u32>; 3],
+}
+
+unsafe fn pads<'a>() -> &'a mut PadsStruct {
+    &mut *(pads_base() as *mut PadsStruct)
+}
+
+// PADS
+const PADS_DRIVE: u32 = 0b111;
+const PADS_HYST: u32 = 1 << 3;
+const PADS_SLEW: u32 = 1 << 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveStrength {
+    Ma2 = 0,
+    Ma4 = 1,
+    Ma6 = 2,
+    Ma8 = 3,
+    Ma10 = 4,
+    Ma12 = 5,
+    Ma14 = 6,
+    Ma16 = 7,
+}
+
+/// Electrical settings shared by all the pins of a bank. The default is
+/// the reset state (0x1B): 8mA, hysteresis on, slew rate not limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PadConfig {
+    pub drive: DriveStrength,
+    /// Schmitt trigger on the inputs
+    pub hysteresis: bool,
+    pub slew_limited: bool,
+}
+
+impl Default for PadConfig {
+    fn default() -> Self {
+        PadConfig {
+            drive: DriveStrength::Ma8,
+            hysteresis: true,
+            slew_limited: false,
+        }
+    }
+}
+
+impl PadConfig {
+    /// Bank holding the pad of GPIO `pin`
+    pub fn bank(pin: usize) -> usize {
+        match pin {
+            0..=27 => 0,
+            28..=45 => 1,
+            _ => 2,
+        }
+    }
+
+    pub unsafe fn read(bank: usize) -> Self {
+        let value = pads().PADS[bank].read();
+        let drive = match value & PADS_DRIVE {
+            0 => DriveStrength::Ma2,
+            1 => DriveStrength::Ma4,
+            2 => DriveStrength::Ma6,
+            3 => DriveStrength::Ma8,
+            4 => DriveStrength::Ma10,
+            5 => DriveStrength::Ma12,
+            6 => DriveStrength::Ma14,
+            _ => DriveStrength::Ma16,
+        };
+        PadConfig {
+            drive,
+            hysteresis: value & PADS_HYST != 0,
+            // The bit enables fast slewing
+            slew_limited: value & PADS_SLEW == 0,
+        }
+    }
+
+    /// Applies the settings to every pin of `bank`.
+    pub unsafe fn write(self, bank: usize) {
+        let mut value = PADS_PASSWORD | self.drive as u32;
+        if self.hysteresis {
+            value |= PADS_HYST;
+        }
+        if !self.slew_limited {
+            value |= PADS_SLEW;
+        }
+        pads().PADS[bank].write(value);
+    }
+
+    pub fn with_drive(mut self, drive: DriveStrength) -> Self {
+        self.drive = drive;
+        self
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: bool) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    pub fn with_slew_limited(mut self, slew_limited: bool) -> Self {
+        self.slew_limited = slew_limited;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Function {
     Input = 0b000,
     Output = 0b001,
@@ -76,13 +201,49 @@ pub enum Function {
     Alternate5 = 0b010,
 }
 
-#[derive(Clone, Copy)]
+impl Function {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b111 {
+            0b000 => Function::Input,
+            0b001 => Function::Output,
+            0b100 => Function::Alternate0,
+            0b101 => Function::Alternate1,
+            0b110 => Function::Alternate2,
+            0b111 => Function::Alternate3,
+            0b011 => Function::Alternate4,
+            _ => Function::Alternate5,
+        }
+    }
+
+    /// 0 to 5 for the alternate functions
+    pub fn alternate(self) -> Option<usize> {
+        match self {
+            Function::Input | Function::Output => None,
+            Function::Alternate0 => Some(0),
+            Function::Alternate1 => Some(1),
+            Function::Alternate2 => Some(2),
+            Function::Alternate3 => Some(3),
+            Function::Alternate4 => Some(4),
+            Function::Alternate5 => Some(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Resistor {
     No = 0b00,
     PullUp = 0b01,
     PullDown = 0b10,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullError {
+    /// The BCM2837 has no pull readback
+    NotReadable,
+    /// The register holds the reserved `0b11`
+    Reserved,
+}
+
 pub struct GPIO(pub usize);
 impl GPIO {
     pub unsafe fn set(&mut self, v: bool) -> &mut Self {
@@ -110,31 +271,54 @@ impl GPIO {
             .write_with_mask_at(function as u32, 0b111, ((self.0 % 10) * 3) as u32);
         self
     }
+    pub unsafe fn function(&self) -> Function {
+        let fsel = gpio().GPFSEL.get_unchecked_mut(self.0 / 10).read();
+        Function::from_bits(fsel >> ((self.0 % 10) * 3))
+    }
+    /// Applies a pull, through `GPIO_PUP_PDN_CNTRL` on the BCM2711 or the
+    /// legacy GPPUD/GPPUDCLK sequence on the BCM2837.
     pub unsafe fn set_pullup_pulldown(&mut self, pup_pdn: Resistor) -> &mut Self {
-        gpio()
+        if board::current() == Board::RaspberryPi3 {
+            let mut clocks = [0; 2];
+            clocks[self.0 / 32] = 1 << (self.0 % 32);
+            legacy_pull(pup_pdn, clocks);
+        } else {
+            gpio()
+                .GPPUD
+                .CNTRL_OFF
+                .GPIO_PUP_PDN_CNTRL
+                .get_unchecked_mut(self.0 / 16)
+                .write_with_mask_at(pup_pdn as u32, 0x3, ((self.0 % 16) * 2) as u32);
+        }
+
+        self
+    }
+    /// Pull currently applied. Fails on a Pi 3, where it can't be read
+    /// back, and for the reserved encoding.
+    pub unsafe fn pullup_pulldown(&self) -> Result<Resistor, PullError> {
+        if board::current() == Board::RaspberryPi3 {
+            return Err(PullError::NotReadable);
+        }
+        let cntrl = gpio()
             .GPPUD
             .CNTRL_OFF
             .GPIO_PUP_PDN_CNTRL
             .get_unchecked_mut(self.0 / 16)
-            .write_with_mask_at(pup_pdn as u32, 0x3, ((self.0 % 16) * 2) as u32);
-
-        // gpio().GPPUD.CLK.GPPUD.write(pup_pdn.value());
-        // crate::asm::delay(150);
-        // gpio()
-        //     .GPPUD
-        //     .CLK
-        //     .GPPUDCLK
-        //     .get_unchecked_mut(self.0 / 32)
-        //     .write(1 << (self.0 % 32));
-        // crate::asm::delay(150);
-        // // gpio().GPPUD.CLK.GPPUD.write(0);
-        // gpio()
-        //     .GPPUD
-        //     .CLK
-        //     .GPPUDCLK
-        //     .get_unchecked_mut(self.0 / 32)
-        //     .write(0);
-
+            .read();
+        match (cntrl >> ((self.0 % 16) * 2)) & 0b11 {
+            0b00 => Ok(Resistor::No),
+            0b01 => Ok(Resistor::PullUp),
+            0b10 => Ok(Resistor::PullDown),
+            _ => Err(PullError::Reserved),
+        }
+    }
+    /// Settings of the pad bank of this pin
+    pub unsafe fn pad_config(&self) -> PadConfig {
+        PadConfig::read(PadConfig::bank(self.0))
+    }
+    /// Changes the pad settings of the whole bank of this pin.
+    pub unsafe fn set_pad_config(&mut self, config: PadConfig) -> &mut Self {
+        config.write(PadConfig::bank(self.0));
         self
     }
     pub unsafe fn rising_edge_detect(&mut self, v: bool) -> &mut Self {
@@ -171,19 +355,392 @@ impl GPIO {
     }
 }
 
-pub trait GPIOTuple {
-    unsafe fn set_function(&mut self, function: Function) -> &mut Self;
-    unsafe fn set_pullup_pulldown(&mut self, pup_pdn: Resistor) -> &mut Self;
+/// 32 pins read or written at once: GPIO 0–31 and 32–57
+pub struct GpioBank(pub usize);
+impl GpioBank {
+    /// Drives the pins of `set_mask` high then those of `clear_mask` low,
+    /// each with a single write. Other pins are left alone.
+    pub unsafe fn write_masked(&mut self, set_mask: u32, clear_mask: u32) -> &mut Self {
+        if set_mask != 0 {
+            gpio().GPSET.value.get_unchecked_mut(self.0).write(set_mask);
+        }
+        if clear_mask != 0 {
+            gpio()
+                .GPCLR
+                .value
+                .get_unchecked_mut(self.0)
+                .write(clear_mask);
+        }
+        self
+    }
+    /// Outputs the bits of `value` selected by `mask`.
+    pub unsafe fn write(&mut self, value: u32, mask: u32) -> &mut Self {
+        self.write_masked(value & mask, !value & mask)
+    }
+    /// Levels of all the pins of the bank
+    pub unsafe fn read_all(&self) -> u32 {
+        gpio().GPLEV.value.get_unchecked_mut(self.0).read()
+    }
+}
+
+/// `PINS` pins spread over any banks, read and written as one value with
+/// bit `i` on `pins[i]`. Writes take one GPSET and one GPCLR access per
+/// bank involved, reads one GPLEV access.
+pub struct PortN<const PINS: usize> {
+    pins: [usize; PINS],
+    masks: [u32; 2],
 }
-impl GPIOTuple for (GPIO, GPIO) {
+impl<const PINS: usize> PortN<PINS> {
+    pub fn new(pins: [usize; PINS]) -> Self {
+        assert!(PINS <= 32);
+        let mut masks = [0; 2];
+        for &pin in pins.iter() {
+            masks[pin / 32] |= 1 << (pin % 32);
+        }
+        PortN { pins, masks }
+    }
+    pub fn pins(&self) -> &[usize; PINS] {
+        &self.pins
+    }
+    pub unsafe fn set_function(&mut self, function: Function) -> &mut Self {
+        for &pin in self.pins.iter() {
+            GPIO(pin).set_function(function);
+        }
+        self
+    }
+    /// Outputs the low `PINS` bits of `value`.
+    pub unsafe fn write(&mut self, value: u32) -> &mut Self {
+        let mut set = [0; 2];
+        for (i, &pin) in self.pins.iter().enumerate() {
+            if value & (1 << i) != 0 {
+                set[pin / 32] |= 1 << (pin % 32);
+            }
+        }
+        for bank in 0..2 {
+            if self.masks[bank] != 0 {
+                GpioBank(bank).write_masked(set[bank], self.masks[bank] & !set[bank]);
+            }
+        }
+        self
+    }
+    pub unsafe fn read(&self) -> u32 {
+        let mut levels = [0; 2];
+        for bank in 0..2 {
+            if self.masks[bank] != 0 {
+                levels[bank] = GpioBank(bank).read_all();
+            }
+        }
+        let mut value = 0;
+        for (i, &pin) in self.pins.iter().enumerate() {
+            if levels[pin / 32] & (1 << (pin % 32)) != 0 {
+                value |= 1 << i;
+            }
+        }
+        value
+    }
+}
+
+/// Latches `pup_pdn` into the pins of the two banks set in `clocks` with
+/// the BCM2837 GPPUD/GPPUDCLK sequence. The control signal needs 150
+/// cycles of the 250MHz core clock of setup and hold around the clock
+/// pulse, which 1us covers.
+unsafe fn legacy_pull(pup_pdn: Resistor, clocks: [u32; 2]) {
+    let clk = &mut gpio().GPPUD.CLK;
+    let gppud = match pup_pdn {
+        Resistor::No => 0b00,
+        Resistor::PullDown => 0b01,
+        Resistor::PullUp => 0b10,
+    };
+    clk.GPPUD.write(gppud);
+    time::wait_us(1);
+    for (bank, &mask) in clocks.iter().enumerate() {
+        if mask != 0 {
+            clk.GPPUDCLK[bank].write(mask);
+        }
+    }
+    time::wait_us(1);
+    clk.GPPUD.write(0);
+    for (bank, &mask) in clocks.iter().enumerate() {
+        if mask != 0 {
+            clk.GPPUDCLK[bank].write(0);
+        }
+    }
+}
+
+/// Sets `field`-bit wide fields of `registers`, `per_register` pins to a
+/// register, to `value` for every pin of `pins`. Each register is read and
+/// written once at most.
+unsafe fn write_fields<G: GPIOGroup + ?Sized>(
+    group: &G,
+    registers: &mut [Register<u32>],
+    per_register: usize,
+    field: u32,
+    value: u32,
+) {
+    let mut masks = [0u32; 6];
+    group.for_each_pin(|pin| {
+        masks[pin / per_register] |= ((1 << field) - 1) << ((pin % per_register) as u32 * field)
+    });
+    for (register, &mask) in registers.iter_mut().zip(masks.iter()) {
+        if mask != 0 {
+            let mut pattern = 0;
+            for i in 0..per_register as u32 {
+                pattern |= value << (i * field);
+            }
+            let current = register.read();
+            register.write(current & !mask | pattern & mask);
+        }
+    }
+}
+
+/// Any set of pins configured together. Operations touch each register
+/// once, however many pins of it the group holds: one GPFSEL write per
+/// ten pins, one GPSET/GPCLR write per bank.
+pub trait GPIOGroup {
+    /// Calls `f` with the number of every pin, in order
+    fn for_each_pin<F: FnMut(usize)>(&self, f: F);
+
+    /// Pins of the group in each bank
+    fn bank_masks(&self) -> [u32; 2] {
+        let mut masks = [0; 2];
+        self.for_each_pin(|pin| masks[pin / 32] |= 1 << (pin % 32));
+        masks
+    }
+
     unsafe fn set_function(&mut self, function: Function) -> &mut Self {
-        self.0.set_function(function);
-        self.1.set_function(function);
+        write_fields(self, &mut gpio().GPFSEL, 10, 3, function as u32);
         self
     }
     unsafe fn set_pullup_pulldown(&mut self, pup_pdn: Resistor) -> &mut Self {
-        self.0.set_pullup_pulldown(pup_pdn);
-        self.1.set_pullup_pulldown(pup_pdn);
+        if board::current() == Board::RaspberryPi3 {
+            legacy_pull(pup_pdn, self.bank_masks());
+        } else {
+            let cntrl = &mut gpio().GPPUD.CNTRL_OFF.GPIO_PUP_PDN_CNTRL;
+            write_fields(self, cntrl, 16, 2, pup_pdn as u32);
+        }
+        self
+    }
+    /// Drives every pin of the group to `v`.
+    unsafe fn set(&mut self, v: bool) -> &mut Self {
+        for (bank, &mask) in self.bank_masks().iter().enumerate() {
+            if mask != 0 {
+                let (set, clear) = if v { (mask, 0) } else { (0, mask) };
+                GpioBank(bank).write_masked(set, clear);
+            }
+        }
+        self
+    }
+    /// Levels of the pins, bit `i` for the `i`th pin of the group. Up to
+    /// 64 pins.
+    unsafe fn get(&self) -> u64 {
+        let masks = self.bank_masks();
+        let mut levels = [0; 2];
+        for bank in 0..2 {
+            if masks[bank] != 0 {
+                levels[bank] = GpioBank(bank).read_all();
+            }
+        }
+        let mut value = 0;
+        let mut i = 0;
+        self.for_each_pin(|pin| {
+            if levels[pin / 32] & (1 << (pin % 32)) != 0 {
+                value |= 1 << i;
+            }
+            i += 1;
+        });
+        value
+    }
+    unsafe fn rising_edge_detect(&mut self, v: bool) -> &mut Self {
+        update_banks(&mut gpio().GPREN, self.bank_masks(), v);
         self
     }
+    unsafe fn falling_edge_detect(&mut self, v: bool) -> &mut Self {
+        update_banks(&mut gpio().GPFEN, self.bank_masks(), v);
+        self
+    }
+    unsafe fn high_edge_detect(&mut self, v: bool) -> &mut Self {
+        update_banks(&mut gpio().GPHEN, self.bank_masks(), v);
+        self
+    }
+    unsafe fn low_edge_detect(&mut self, v: bool) -> &mut Self {
+        update_banks(&mut gpio().GPLEN, self.bank_masks(), v);
+        self
+    }
+    /// Writes `config` to every pad bank the group has a pin in. Other
+    /// pins of these banks change as well.
+    unsafe fn set_pad_config(&mut self, config: PadConfig) -> &mut Self {
+        let mut banks = [false; 3];
+        self.for_each_pin(|pin| banks[PadConfig::bank(pin)] = true);
+        for (bank, &used) in banks.iter().enumerate() {
+            if used {
+                config.write(bank);
+            }
+        }
+        self
+    }
+}
+
+unsafe fn update_banks(access: &mut GPIOAccess, masks: [u32; 2], v: bool) {
+    for (register, &mask) in access.value.iter_mut().zip(masks.iter()) {
+        if mask != 0 {
+            let current = register.read();
+            register.write(if v { current | mask } else { current & !mask });
+        }
+    }
+}
+
+/// Former name of `GPIOGroup`
+pub use self::GPIOGroup as GPIOTuple;
+
+impl GPIOGroup for GPIO {
+    fn for_each_pin<F: FnMut(usize)>(&self, mut f: F) {
+        f(self.0)
+    }
+}
+
+impl GPIOGroup for [GPIO] {
+    fn for_each_pin<F: FnMut(usize)>(&self, mut f: F) {
+        for pin in self {
+            f(pin.0)
+        }
+    }
+}
+
+impl<const N: usize> GPIOGroup for [GPIO; N] {
+    fn for_each_pin<F: FnMut(usize)>(&self, f: F) {
+        self[..].for_each_pin(f)
+    }
+}
+
+macro_rules! impl_gpio_group_tuple {
+    ($($field:tt: $ty:ident),+) => {
+        impl GPIOGroup for ($($ty,)+) {
+            fn for_each_pin<F: FnMut(usize)>(&self, mut f: F) {
+                $(f((self.$field).0);)+
+            }
+        }
+    };
+}
+
+impl_gpio_group_tuple!(0: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO, 2: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO, 6: GPIO);
+impl_gpio_group_tuple!(0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO, 6: GPIO, 7: GPIO);
+impl_gpio_group_tuple!(
+    0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO, 6: GPIO, 7: GPIO, 8: GPIO
+);
+impl_gpio_group_tuple!(
+    0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO, 6: GPIO, 7: GPIO, 8: GPIO, 9: GPIO
+);
+impl_gpio_group_tuple!(
+    0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO, 6: GPIO, 7: GPIO, 8: GPIO, 9: GPIO,
+    10: GPIO
+);
+impl_gpio_group_tuple!(
+    0: GPIO, 1: GPIO, 2: GPIO, 3: GPIO, 4: GPIO, 5: GPIO, 6: GPIO, 7: GPIO, 8: GPIO, 9: GPIO,
+    10: GPIO, 11: GPIO
+);
+
+/// Peripheral signal behind each alternate function of GPIO 0–45 on the
+/// BCM2711, "-" where unused.
+#[rustfmt::skip]
+static ALT_FUNCTIONS: [[&str; 6]; 46] = [
+    ["SDA0", "SA5", "PCLK", "SPI3_CE0_N", "TXD2", "SDA6"],
+    ["SCL0", "SA4", "DE", "SPI3_MISO", "RXD2", "SCL6"],
+    ["SDA1", "SA3", "LCD_VSYNC", "SPI3_MOSI", "CTS2", "SDA3"],
+    ["SCL1", "SA2", "LCD_HSYNC", "SPI3_SCLK", "RTS2", "SCL3"],
+    ["GPCLK0", "SA1", "DPI_D0", "SPI4_CE0_N", "TXD3", "SDA3"],
+    ["GPCLK1", "SA0", "DPI_D1", "SPI4_MISO", "RXD3", "SCL3"],
+    ["GPCLK2", "SOE_N", "DPI_D2", "SPI4_MOSI", "CTS3", "SDA4"],
+    ["SPI0_CE1_N", "SWE_N", "DPI_D3", "SPI4_SCLK", "RTS3", "SCL4"],
+    ["SPI0_CE0_N", "SD0", "DPI_D4", "BSCSL_CE_N", "TXD4", "SDA4"],
+    ["SPI0_MISO", "SD1", "DPI_D5", "BSCSL_MISO", "RXD4", "SCL4"],
+    ["SPI0_MOSI", "SD2", "DPI_D6", "BSCSL_SDA_MOSI", "CTS4", "SDA5"],
+    ["SPI0_SCLK", "SD3", "DPI_D7", "BSCSL_SCL_SCLK", "RTS4", "SCL5"],
+    ["PWM0_0", "SD4", "DPI_D8", "SPI5_CE0_N", "TXD5", "SDA5"],
+    ["PWM0_1", "SD5", "DPI_D9", "SPI5_MISO", "RXD5", "SCL5"],
+    ["TXD0", "SD6", "DPI_D10", "SPI5_MOSI", "CTS5", "TXD1"],
+    ["RXD0", "SD7", "DPI_D11", "SPI5_SCLK", "RTS5", "RXD1"],
+    ["-", "SD8", "DPI_D12", "CTS0", "SPI1_CE2_N", "CTS1"],
+    ["-", "SD9", "DPI_D13", "RTS0", "SPI1_CE1_N", "RTS1"],
+    ["PCM_CLK", "SD10", "DPI_D14", "SPI6_CE0_N", "SPI1_CE0_N", "PWM0_0"],
+    ["PCM_FS", "SD11", "DPI_D15", "SPI6_MISO", "SPI1_MISO", "PWM0_1"],
+    ["PCM_DIN", "SD12", "DPI_D16", "SPI6_MOSI", "SPI1_MOSI", "GPCLK0"],
+    ["PCM_DOUT", "SD13", "DPI_D17", "SPI6_SCLK", "SPI1_SCLK", "GPCLK1"],
+    ["SD0_CLK", "SD14", "DPI_D18", "SD1_CLK", "ARM_TRST", "SDA6"],
+    ["SD0_CMD", "SD15", "DPI_D19", "SD1_CMD", "ARM_RTCK", "SCL6"],
+    ["SD0_DAT0", "SD16", "DPI_D20", "SD1_DAT0", "ARM_TDO", "SPI3_CE1_N"],
+    ["SD0_DAT1", "SD17", "DPI_D21", "SD1_DAT1", "ARM_TCK", "SPI4_CE1_N"],
+    ["SD0_DAT2", "TE0", "DPI_D22", "SD1_DAT2", "ARM_TDI", "SPI5_CE1_N"],
+    ["SD0_DAT3", "TE1", "DPI_D23", "SD1_DAT3", "ARM_TMS", "SPI6_CE1_N"],
+    ["SDA0", "SA5", "PCM_CLK", "-", "MII_A_RX_ERR", "RGMII_MDIO"],
+    ["SCL0", "SA4", "PCM_FS", "-", "MII_A_TX_ERR", "RGMII_MDC"],
+    ["-", "SA3", "PCM_DIN", "CTS0", "MII_A_CRS", "CTS1"],
+    ["-", "SA2", "PCM_DOUT", "RTS0", "MII_A_COL", "RTS1"],
+    ["GPCLK0", "SA1", "-", "TXD0", "SD_CARD_PRES", "TXD1"],
+    ["-", "SA0", "-", "RXD0", "SD_CARD_WRPROT", "RXD1"],
+    ["GPCLK0", "SOE_N", "-", "SD1_CLK", "SD_CARD_LED", "RGMII_IRQ"],
+    ["SPI0_CE1_N", "SWE_N", "-", "SD1_CMD", "RGMII_START_STOP", "-"],
+    ["SPI0_CE0_N", "SD0", "TXD0", "SD1_DAT0", "RGMII_RX_OK", "MII_A_RX_ERR"],
+    ["SPI0_MISO", "SD1", "RXD0", "SD1_DAT1", "RGMII_MDIO", "MII_A_TX_ERR"],
+    ["SPI0_MOSI", "SD2", "RTS0", "SD1_DAT2", "RGMII_MDC", "MII_A_CRS"],
+    ["SPI0_SCLK", "SD3", "CTS0", "SD1_DAT3", "RGMII_IRQ", "MII_A_COL"],
+    ["PWM1_0", "SD4", "-", "SD1_DAT4", "SPI2_MISO", "TXD1"],
+    ["PWM1_1", "SD5", "-", "SD1_DAT5", "SPI2_MOSI", "RXD1"],
+    ["GPCLK1", "SD6", "-", "SD1_DAT6", "SPI2_SCLK", "RTS1"],
+    ["GPCLK2", "SD7", "-", "SD1_DAT7", "SPI2_CE0_N", "CTS1"],
+    ["GPCLK1", "SDA0", "SDA1", "-", "SPI2_CE1_N", "SD_CARD_VOLT"],
+    ["PWM0_1", "SCL0", "SCL1", "-", "SPI2_CE2_N", "SD_CARD_PWR0"],
+];
+
+/// Name of the BCM2711 signal `function` selects on GPIO `pin`, if known.
+pub fn alt_function_name(pin: usize, function: Function) -> Option<&'static str> {
+    let name = ALT_FUNCTIONS.get(pin)?[function.alternate()?];
+    if name == "-" {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Prints the level, function and pull of every pin to the UART, in the
+/// spirit of `raspi-gpio get`. Signal names come from the BCM2711 table
+/// and are left out on a Pi 3.
+pub fn dump() {
+    let (count, names) = match board::current() {
+        Board::RaspberryPi3 => (54, false),
+        Board::RaspberryPi4 => (58, true),
+    };
+    for pin in 0..count {
+        let gpio = GPIO(pin);
+        let (level, function, pull) =
+            unsafe { (gpio.get(), gpio.function(), gpio.pullup_pulldown()) };
+
+        // One lock per line, so that lines from other cores don't cut in
+        let mut console = uart::console();
+        let _ = write!(console, "GPIO {:2}: level={} ", pin, level as u8);
+        let _ = match function {
+            Function::Input => write!(console, "fsel=0 func=INPUT"),
+            Function::Output => write!(console, "fsel=1 func=OUTPUT"),
+            _ => {
+                let alt = function.alternate().unwrap_or(0);
+                let _ = write!(console, "fsel={} alt={}", function as u32, alt);
+                match alt_function_name(pin, function).filter(|_| names) {
+                    Some(name) => write!(console, " func={}", name),
+                    None => write!(console, " func=ALT{}", alt),
+                }
+            }
+        };
+        let _ = match pull {
+            Ok(Resistor::No) => write!(console, " pull=NONE\r\n"),
+            Ok(Resistor::PullUp) => write!(console, " pull=UP\r\n"),
+            Ok(Resistor::PullDown) => write!(console, " pull=DOWN\r\n"),
+            Err(PullError::Reserved) => write!(console, " pull=RESERVED\r\n"),
+            Err(PullError::NotReadable) => write!(console, "\r\n"),
+        };
+    }
 }
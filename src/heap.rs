@@ -0,0 +1,234 @@
+//! Global allocator, enabled by the `alloc` feature.
+//!
+//! The heap spans the ARM memory reported by the firmware, from the end
+//! of the kernel image (`__kernel_end`, which the bundled `link.ld` places
+//! after `.bss` and the core stacks) to the VideoCore split. The device
+//! tree blob, when known, is left out as well. Call `init` once before the
+//! first allocation; allocations fail until then.
+//!
+//! Free memory is kept in an address-ordered list of holes, allocation is
+//! first-fit and freed blocks are merged with their neighbours. Blocks are
+//! multiples of 16 bytes.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::null_mut,
+};
+
+use super::sync::IrqSpinLock;
+
+const MIN_ALIGN: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    /// Bytes handed to the allocator
+    pub total: usize,
+    /// Bytes currently allocated, rounding included
+    pub used: usize,
+    /// Highest value of `used`
+    pub peak: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    /// Allocations that could not be satisfied
+    pub failures: usize,
+}
+
+impl Stats {
+    pub fn free(&self) -> usize {
+        self.total - self.used
+    }
+}
+
+struct Hole {
+    size: usize,
+    next: *mut Hole,
+}
+
+pub struct LinkedListHeap {
+    /// Dummy hole of size 0 heading the list
+    head: Hole,
+    stats: Stats,
+}
+
+unsafe impl Send for LinkedListHeap {}
+
+#[inline]
+fn align_up(v: usize, align: usize) -> usize {
+    (v + align - 1) & !(align - 1)
+}
+
+#[inline]
+fn align_down(v: usize, align: usize) -> usize {
+    v & !(align - 1)
+}
+
+impl LinkedListHeap {
+    pub const fn empty() -> Self {
+        LinkedListHeap {
+            head: Hole {
+                size: 0,
+                next: null_mut(),
+            },
+            stats: Stats {
+                total: 0,
+                used: 0,
+                peak: 0,
+                allocations: 0,
+                deallocations: 0,
+                failures: 0,
+            },
+        }
+    }
+
+    #[inline]
+    fn block_size(layout: Layout) -> usize {
+        align_up(layout.size().max(size_of::<Hole>()), MIN_ALIGN)
+    }
+
+    /// Gives `[start, start + size)` to the allocator. The memory must be
+    /// unused and stay reserved to the heap from now on.
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        let aligned = align_up(start, MIN_ALIGN);
+        let end = align_down(start + size, MIN_ALIGN);
+        if end > aligned {
+            self.insert(aligned, end - aligned);
+            self.stats.total += end - aligned;
+        }
+    }
+
+    unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let head: *mut Hole = &mut self.head;
+        let mut prev = head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < addr {
+            prev = (*prev).next;
+        }
+
+        let next = (*prev).next;
+        let hole = addr as *mut Hole;
+        hole.write(Hole { size, next });
+        (*prev).next = hole;
+
+        if !next.is_null() && addr + size == next as usize {
+            (*hole).size += (*next).size;
+            (*hole).next = (*next).next;
+        }
+        if prev != head && prev as usize + (*prev).size == addr {
+            (*prev).size += (*hole).size;
+            (*prev).next = (*hole).next;
+        }
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = Self::block_size(layout);
+        let align = layout.align().max(MIN_ALIGN);
+
+        unsafe {
+            let mut prev: *mut Hole = &mut self.head;
+            while !(*prev).next.is_null() {
+                let hole = (*prev).next;
+                let start = hole as usize;
+                let end = start + (*hole).size;
+                let addr = align_up(start, align);
+
+                if addr + size <= end {
+                    let next = (*hole).next;
+                    (*prev).next = next;
+                    if addr > start {
+                        (*hole).size = addr - start;
+                        (*prev).next = hole;
+                        prev = hole;
+                    }
+                    if end > addr + size {
+                        let back = (addr + size) as *mut Hole;
+                        back.write(Hole {
+                            size: end - addr - size,
+                            next,
+                        });
+                        (*prev).next = back;
+                    }
+
+                    self.stats.used += size;
+                    self.stats.peak = self.stats.peak.max(self.stats.used);
+                    self.stats.allocations += 1;
+                    return addr as *mut u8;
+                }
+                prev = hole;
+            }
+        }
+
+        self.stats.failures += 1;
+        null_mut()
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = Self::block_size(layout);
+        self.insert(ptr as usize, size);
+        self.stats.used -= size;
+        self.stats.deallocations += 1;
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+pub struct Heap(IrqSpinLock<LinkedListHeap>);
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().allocate(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().deallocate(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static HEAP: Heap = Heap(IrqSpinLock::new(LinkedListHeap::empty()));
+
+/// Hands the free ARM memory to the global allocator.
+pub unsafe fn init() {
+    extern "C" {
+        static __kernel_end: u8;
+    }
+
+    let memory = super::memory();
+    let start = &__kernel_end as *const u8 as usize;
+    let end = memory.ptr as usize + memory.bytes;
+
+    let reserved = dtb_range();
+
+    // The blob may straddle either end of the heap, only the overlap is
+    // left out. `add_region` shrinks both pieces to MIN_ALIGN.
+    let (below, above) = (reserved.0.max(start), reserved.1.min(end));
+
+    let mut heap = HEAP.0.lock();
+    if below < above {
+        heap.add_region(start, below - start);
+        heap.add_region(above, end - above);
+    } else {
+        heap.add_region(start, end - start);
+    }
+}
+
+unsafe fn dtb_range() -> (usize, usize) {
+    let fdt = super::dtb::get();
+    #[cfg(feature = "rt")]
+    let fdt = fdt.or_else(|| match super::rt::dtb_ptr() {
+        0 => None,
+        ptr => super::dtb::Fdt::from_ptr(ptr as *const u8).ok(),
+    });
+
+    match fdt {
+        Some(fdt) => (
+            fdt.as_ptr() as usize,
+            fdt.as_ptr() as usize + fdt.total_size(),
+        ),
+        None => (0, 0),
+    }
+}
+
+pub fn stats() -> Stats {
+    HEAP.0.lock().stats()
+}
@@ -0,0 +1,389 @@
+//! I2C masters (Broadcom Serial Controllers).
+//!
+//! BSC0 and BSC1 exist on both boards, BSC3 to BSC6 on the BCM2711 only.
+//! BSC2 and BSC7 belong to the HDMI ports. Each controller has a 16-byte
+//! FIFO and moves `DLEN` bytes per start condition.
+//!
+//! A start queued while a transfer is still active turns into a repeated
+//! start, which is how writes are chained to the following operation of
+//! an `I2c::transaction`. Operations after a read get a stop and a new
+//! start, the controller cannot hold the bus once a read has drained.
+
+use embedded_hal::i2c::{
+    self, ErrorKind, ErrorType, NoAcknowledgeSource, Operation, SevenBitAddress, TenBitAddress,
+};
+use register::*;
+
+use super::{
+    board::{self, Board},
+    gpio::{Function, GPIO},
+};
+
+// C
+const C_READ: u32 = 1 << 0;
+const C_CLEAR: u32 = 0b11 << 4;
+const C_ST: u32 = 1 << 7;
+const C_I2CEN: u32 = 1 << 15;
+
+// S
+const S_TA: u32 = 1 << 0;
+const S_DONE: u32 = 1 << 1;
+const S_TXD: u32 = 1 << 4;
+const S_RXD: u32 = 1 << 5;
+const S_ERR: u32 = 1 << 8;
+const S_CLKT: u32 = 1 << 9;
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct BSCStruct {
+    pub C: Register<u32>,
+    pub S: Register<u32>,
+    pub DLEN: Register<u32>,
+    pub A: Register<u32>,
+    pub FIFO: Register<u32>,
+    pub DIV: Register<u32>,
+    pub DEL: Register<u32>,
+    pub CLKT: Register<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Bsc0,
+    Bsc1,
+    /// BCM2711 only, like the following ones
+    Bsc3,
+    Bsc4,
+    Bsc5,
+    Bsc6,
+}
+
+impl Bus {
+    fn offset(self) -> usize {
+        match self {
+            Bus::Bsc0 => 0x20_5000,
+            Bus::Bsc1 => 0x80_4000,
+            Bus::Bsc3 => 0x20_5600,
+            Bus::Bsc4 => 0x20_5800,
+            Bus::Bsc5 => 0x20_5A80,
+            Bus::Bsc6 => 0x20_5C00,
+        }
+    }
+
+    /// SDA, SCL and their function
+    fn pins(self) -> (usize, usize, Function) {
+        match self {
+            Bus::Bsc0 => (0, 1, Function::Alternate0),
+            Bus::Bsc1 => (2, 3, Function::Alternate0),
+            Bus::Bsc3 => (4, 5, Function::Alternate5),
+            Bus::Bsc4 => (6, 7, Function::Alternate5),
+            Bus::Bsc5 => (10, 11, Function::Alternate5),
+            Bus::Bsc6 => (22, 23, Function::Alternate5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// 100kHz
+    Standard,
+    /// 400kHz
+    Fast,
+    Hz(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The address or a data byte was not acknowledged
+    Nack,
+    /// The slave held SCL low for longer than the configured timeout
+    ClockStretchTimeout,
+    /// The transfer ended before all bytes were moved, without a NACK
+    /// or a timeout: the bus was taken by someone else
+    ArbitrationLoss,
+}
+
+impl i2c::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            Error::ClockStretchTimeout => ErrorKind::Other,
+            Error::ArbitrationLoss => ErrorKind::ArbitrationLoss,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl Address {
+    /// Value for the `A` register
+    fn register(self) -> u32 {
+        match self {
+            Address::SevenBit(a) => a as u32 & 0x7F,
+            // 0b11110 and the two high bits, the low byte goes in the data
+            Address::TenBit(a) => 0x78 | (a as u32 >> 8) & 0b11,
+        }
+    }
+
+    /// Byte sent ahead of the data of a write
+    fn prefix(self) -> Option<u8> {
+        match self {
+            Address::SevenBit(_) => None,
+            Address::TenBit(a) => Some(a as u8),
+        }
+    }
+}
+
+pub struct I2c {
+    bus: Bus,
+    cdiv: u32,
+    clock: u32,
+}
+
+impl I2c {
+    /// Fails for BSC3–6 on a Pi 3. Only one `I2c` must exist per
+    /// controller.
+    pub unsafe fn new(bus: Bus) -> Result<Self, ()> {
+        match bus {
+            Bus::Bsc0 | Bus::Bsc1 => {}
+            _ if board::current() == Board::RaspberryPi3 => return Err(()),
+            _ => {}
+        }
+        let mut i2c = I2c {
+            bus,
+            cdiv: 0,
+            clock: board::current().core_clock(),
+        };
+        i2c.regs().C.write(C_I2CEN | C_CLEAR);
+        i2c.regs().S.write(S_CLKT | S_ERR | S_DONE);
+        i2c.set_speed(Speed::Standard);
+        Ok(i2c)
+    }
+
+    #[inline]
+    fn regs(&mut self) -> &mut BSCStruct {
+        let base = super::peripherals_base() + self.bus.offset();
+        unsafe { &mut *(base as *mut BSCStruct) }
+    }
+
+    /// Muxes SDA and SCL. The Pi only has pull-ups on BSC1.
+    pub unsafe fn init_pins(&mut self) -> &mut Self {
+        let (sda, scl, function) = self.bus.pins();
+        GPIO(sda).set_function(function);
+        GPIO(scl).set_function(function);
+        self
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) -> &mut Self {
+        let hz = match speed {
+            Speed::Standard => 100_000,
+            Speed::Fast => 400_000,
+            Speed::Hz(hz) => hz.max(1),
+        };
+        self.clock = board::core_clock();
+        let cdiv = (self.clock + hz - 1) / hz;
+        self.set_divider(((cdiv + 1) & !1).min(0xFFFE))
+    }
+
+    /// Sets `DIV` directly: SCL = core clock / `cdiv`, rounded down to an
+    /// even value by the controller. Data is sampled and changed a
+    /// quarter and a sixteenth of a period after the clock edges.
+    pub fn set_divider(&mut self, cdiv: u32) -> &mut Self {
+        self.cdiv = cdiv & 0xFFFE;
+        let falling = (self.cdiv / 16).max(1);
+        let rising = (self.cdiv / 4).max(1);
+        let cdiv = self.cdiv;
+        let regs = self.regs();
+        unsafe {
+            regs.DIV.write(cdiv);
+            regs.DEL.write(falling << 16 | rising);
+        }
+        self
+    }
+
+    pub fn frequency(&self) -> u32 {
+        match self.cdiv {
+            0 => self.clock / 0x8000,
+            cdiv => self.clock / cdiv,
+        }
+    }
+
+    /// SCL periods a slave may stretch the clock for before the transfer
+    /// is abandoned with `Error::ClockStretchTimeout`. 0 waits forever.
+    pub fn set_clock_stretch_timeout(&mut self, scl_periods: u16) -> &mut Self {
+        unsafe { self.regs().CLKT.write(scl_periods as u32) };
+        self
+    }
+
+    /// Queues a start, repeated if a transfer is still active.
+    fn start(&mut self, address: Address, len: usize, read: bool) {
+        assert!(len <= 0xFFFF);
+        let regs = self.regs();
+        unsafe {
+            regs.A.write(address.register());
+            regs.DLEN.write(len as u32);
+            regs.C.write(C_I2CEN | C_ST | if read { C_READ } else { 0 });
+        }
+    }
+
+    fn status(&mut self) -> Result<u32, Error> {
+        let s = unsafe { self.regs().S.read() };
+        if s & S_ERR != 0 {
+            Err(Error::Nack)
+        } else if s & S_CLKT != 0 {
+            Err(Error::ClockStretchTimeout)
+        } else {
+            Ok(s)
+        }
+    }
+
+    fn write_bytes<I: Iterator<Item = u8>>(
+        &mut self,
+        address: Address,
+        len: usize,
+        mut bytes: I,
+    ) -> Result<(), Error> {
+        self.start(address, len, false);
+        let mut sent = 0;
+        while sent < len {
+            let s = self.status()?;
+            if s & S_TXD != 0 {
+                let byte = bytes.next().unwrap_or(0);
+                unsafe { self.regs().FIFO.write(byte as u32) };
+                sent += 1;
+            } else if s & S_DONE != 0 {
+                return Err(Error::ArbitrationLoss);
+            }
+        }
+        // Wait for the start to be taken, so that the next one repeats it
+        while self.status()? & (S_TA | S_DONE) == 0 {}
+        Ok(())
+    }
+
+    fn read_bytes<'a, I: Iterator<Item = &'a mut u8>>(
+        &mut self,
+        address: Address,
+        len: usize,
+        mut slots: I,
+    ) -> Result<(), Error> {
+        self.start(address, len, true);
+        let mut received = 0;
+        while received < len {
+            let s = self.status()?;
+            if s & S_RXD != 0 {
+                let byte = unsafe { self.regs().FIFO.read() } as u8;
+                if let Some(slot) = slots.next() {
+                    *slot = byte;
+                }
+                received += 1;
+            } else if s & S_DONE != 0 {
+                return Err(Error::ArbitrationLoss);
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, address: Address, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        // Nothing starts, so DONE would never be set
+        if operations.is_empty() {
+            return Ok(());
+        }
+        let mut first = true;
+        let mut i = 0;
+        while i < operations.len() {
+            let read = matches!(operations[i], Operation::Read(_));
+            let end = operations[i..]
+                .iter()
+                .position(|op| matches!(op, Operation::Read(_)) != read)
+                .map_or(operations.len(), |n| i + n);
+            let group = &mut operations[i..end];
+            let prefix = address.prefix();
+
+            if read {
+                // A 10-bit read needs the full address written first
+                if first && prefix.is_some() {
+                    self.write_bytes(address, 1, prefix.into_iter())?;
+                }
+                let len = group.iter().map(len_of).sum();
+                let slots = group.iter_mut().flat_map(|op| match op {
+                    Operation::Read(b) => b.iter_mut(),
+                    _ => (&mut []).iter_mut(),
+                });
+                self.read_bytes(address, len, slots)?;
+            } else {
+                let len = group.iter().map(len_of).sum::<usize>() + prefix.iter().count();
+                let bytes = group.iter().flat_map(|op| match op {
+                    Operation::Write(b) => b.iter(),
+                    _ => (&[]).iter(),
+                });
+                self.write_bytes(address, len, prefix.into_iter().chain(bytes.copied()))?;
+            }
+
+            first = false;
+            i = end;
+        }
+
+        loop {
+            let s = self.status()?;
+            if s & S_DONE != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn transaction_impl(
+        &mut self,
+        address: Address,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Error> {
+        unsafe {
+            let regs = self.regs();
+            regs.S.write(S_CLKT | S_ERR | S_DONE);
+            regs.C.write(C_I2CEN | C_CLEAR);
+        }
+        let res = self.run(address, operations);
+        unsafe {
+            let regs = self.regs();
+            if res.is_err() {
+                // Abort whatever is left and flush the FIFO
+                regs.C.write(C_I2CEN | C_CLEAR);
+            }
+            regs.S.write(S_CLKT | S_ERR | S_DONE);
+        }
+        res
+    }
+}
+
+fn len_of(op: &Operation<'_>) -> usize {
+    match op {
+        Operation::Read(b) => b.len(),
+        Operation::Write(b) => b.len(),
+    }
+}
+
+impl ErrorType for I2c {
+    type Error = Error;
+}
+
+impl i2c::I2c<SevenBitAddress> for I2c {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Error> {
+        self.transaction_impl(Address::SevenBit(address), operations)
+    }
+}
+
+impl i2c::I2c<TenBitAddress> for I2c {
+    fn transaction(
+        &mut self,
+        address: TenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Error> {
+        self.transaction_impl(Address::TenBit(address), operations)
+    }
+}
@@ -0,0 +1,281 @@
+//! Board-agnostic interrupt controller interface.
+//!
+//! Interrupts are named with [`Irq`], whose numbering follows the legacy
+//! controller: VideoCore interrupts are 0–63, ARMC interrupts 64–71 and
+//! ARM local sources 96–107. The GIC backend translates them to GIC IDs.
+//!
+//! The controller is picked at runtime from the board: the GIC-400 on the
+//! Pi 4, the legacy ARMC on the Pi 3. With the `legacy-interrupts`
+//! feature, for a Pi 4 booted with `enable_gic=0`, the ARMC is used on
+//! both.
+//!
+//! Handlers registered with `register` are called from the IRQ vector
+//! installed by `exception::init`.
+
+use super::{armc::ARMC, board, exception::ExceptionContext, gic::GIC};
+
+const MAX_IRQ: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Irq(pub u32);
+impl Irq {
+    const fn vc(n: u32) -> Self {
+        Irq(n)
+    }
+    const fn armc(n: u32) -> Self {
+        Irq(64 + n)
+    }
+    const fn local(n: u32) -> Self {
+        Irq(96 + n)
+    }
+
+    pub const SECURE_PHYSICAL_TIMER: Self = Self::local(0);
+    pub const PHYSICAL_TIMER: Self = Self::local(1);
+    pub const HYP_TIMER: Self = Self::local(2);
+    pub const VIRTUAL_TIMER: Self = Self::local(3);
+    pub const LOCAL_MAILBOX_0: Self = Self::local(4);
+    pub const LOCAL_MAILBOX_1: Self = Self::local(5);
+    pub const LOCAL_MAILBOX_2: Self = Self::local(6);
+    pub const LOCAL_MAILBOX_3: Self = Self::local(7);
+
+    pub const ARM_TIMER: Self = Self::armc(0);
+    pub const ARM_MAILBOX: Self = Self::armc(1);
+    pub const ARM_DOORBELL_0: Self = Self::armc(2);
+    pub const ARM_DOORBELL_1: Self = Self::armc(3);
+
+    pub const SYSTEM_TIMER_0: Self = Self::vc(0);
+    pub const SYSTEM_TIMER_1: Self = Self::vc(1);
+    pub const SYSTEM_TIMER_2: Self = Self::vc(2);
+    pub const SYSTEM_TIMER_3: Self = Self::vc(3);
+    pub const USB: Self = Self::vc(9);
+    pub const V3D: Self = Self::vc(10);
+    pub const DMA_0: Self = Self::vc(16);
+    pub const DMA_1: Self = Self::vc(17);
+    pub const DMA_2: Self = Self::vc(18);
+    pub const DMA_3: Self = Self::vc(19);
+    pub const DMA_4: Self = Self::vc(20);
+    pub const DMA_5: Self = Self::vc(21);
+    pub const DMA_6: Self = Self::vc(22);
+    pub const DMA_7_8: Self = Self::vc(23);
+    pub const DMA_9_10: Self = Self::vc(24);
+    pub const DMA_11: Self = Self::vc(25);
+    pub const DMA_12: Self = Self::vc(26);
+    pub const DMA_13: Self = Self::vc(27);
+    pub const DMA_14: Self = Self::vc(28);
+    pub const AUX: Self = Self::vc(29);
+    pub const BSC_SLAVE: Self = Self::vc(43);
+    pub const GPIO_BANK_0: Self = Self::vc(49);
+    pub const GPIO_BANK_1: Self = Self::vc(50);
+    pub const GPIO_BANK_2: Self = Self::vc(51);
+    pub const GPIO_ALL: Self = Self::vc(52);
+    pub const I2C: Self = Self::vc(53);
+    pub const SPI: Self = Self::vc(54);
+    pub const PCM: Self = Self::vc(55);
+    pub const SDHOST: Self = Self::vc(56);
+    pub const PL011: Self = Self::vc(57);
+    pub const EMMC2: Self = Self::vc(62);
+}
+
+pub trait Controller {
+    /// Disables every source. Only one core should do this.
+    unsafe fn init(&mut self);
+    /// Per-core setup, every core taking interrupts must call this.
+    unsafe fn init_core(&mut self);
+    unsafe fn enable(&mut self, irq: Irq);
+    unsafe fn disable(&mut self, irq: Irq);
+    /// Acknowledges and returns the next interrupt pending on this core.
+    unsafe fn next(&mut self) -> Option<Irq>;
+    unsafe fn complete(&mut self, irq: Irq);
+}
+
+mod gic_backend {
+    use super::{Controller, Irq};
+    use crate::gic::{Acknowledged, IrqId, GIC};
+
+    fn to_gic(irq: Irq) -> Option<IrqId> {
+        Some(match irq.0 {
+            0..=63 => IrqId(96 + irq.0),
+            64..=71 => IrqId(irq.0),
+            96 => IrqId::SECURE_PHYSICAL_TIMER,
+            97 => IrqId::PHYSICAL_TIMER,
+            98 => IrqId::HYP_TIMER,
+            99 => IrqId::VIRTUAL_TIMER,
+            _ => return None,
+        })
+    }
+
+    fn from_gic(id: IrqId) -> Option<Irq> {
+        Some(match id {
+            IrqId::SECURE_PHYSICAL_TIMER => Irq::SECURE_PHYSICAL_TIMER,
+            IrqId::PHYSICAL_TIMER => Irq::PHYSICAL_TIMER,
+            IrqId::HYP_TIMER => Irq::HYP_TIMER,
+            IrqId::VIRTUAL_TIMER => Irq::VIRTUAL_TIMER,
+            IrqId(n @ 64..=71) => Irq(n),
+            IrqId(n @ 96..=159) => Irq(n - 96),
+            _ => return None,
+        })
+    }
+
+    impl Controller for GIC {
+        unsafe fn init(&mut self) {
+            self.init_distributor();
+        }
+        unsafe fn init_core(&mut self) {
+            self.init_cpu_interface();
+        }
+        unsafe fn enable(&mut self, irq: Irq) {
+            if let Some(id) = to_gic(irq) {
+                GIC::enable(self, id);
+            }
+        }
+        unsafe fn disable(&mut self, irq: Irq) {
+            if let Some(id) = to_gic(irq) {
+                GIC::disable(self, id);
+            }
+        }
+        unsafe fn next(&mut self) -> Option<Irq> {
+            loop {
+                let ack = self.acknowledge()?;
+                match from_gic(ack.id()) {
+                    Some(irq) => break Some(irq),
+                    // Nothing can be registered for it, drop it
+                    None => {
+                        self.end_of_interrupt(ack);
+                    }
+                }
+            }
+        }
+        unsafe fn complete(&mut self, irq: Irq) {
+            if let Some(id) = to_gic(irq) {
+                self.end_of_interrupt(Acknowledged(id.0));
+            }
+        }
+    }
+}
+
+enum Backend {
+    Gic(GIC),
+    Armc(ARMC),
+}
+
+impl Controller for Backend {
+    unsafe fn init(&mut self) {
+        match self {
+            Backend::Gic(c) => Controller::init(c),
+            Backend::Armc(c) => c.init(),
+        }
+    }
+    unsafe fn init_core(&mut self) {
+        match self {
+            Backend::Gic(c) => Controller::init_core(c),
+            Backend::Armc(c) => c.init_core(),
+        }
+    }
+    unsafe fn enable(&mut self, irq: Irq) {
+        match self {
+            Backend::Gic(c) => Controller::enable(c, irq),
+            Backend::Armc(c) => c.enable(irq),
+        }
+    }
+    unsafe fn disable(&mut self, irq: Irq) {
+        match self {
+            Backend::Gic(c) => Controller::disable(c, irq),
+            Backend::Armc(c) => c.disable(irq),
+        }
+    }
+    unsafe fn next(&mut self) -> Option<Irq> {
+        match self {
+            Backend::Gic(c) => Controller::next(c),
+            Backend::Armc(c) => c.next(),
+        }
+    }
+    unsafe fn complete(&mut self, irq: Irq) {
+        match self {
+            Backend::Gic(c) => Controller::complete(c, irq),
+            Backend::Armc(c) => c.complete(irq),
+        }
+    }
+}
+
+/// Controller of the running board
+#[inline]
+pub fn controller() -> impl Controller {
+    if board::current().has_gic() && !cfg!(feature = "legacy-interrupts") {
+        Backend::Gic(GIC)
+    } else {
+        Backend::Armc(ARMC)
+    }
+}
+
+pub unsafe fn init() {
+    let mut c = controller();
+    c.init();
+    c.init_core();
+}
+
+pub unsafe fn init_core() {
+    controller().init_core();
+}
+
+pub unsafe fn enable(irq: Irq) {
+    controller().enable(irq);
+}
+
+pub unsafe fn disable(irq: Irq) {
+    controller().disable(irq);
+}
+
+/// Calls `handler` for every interrupt pending on the calling core,
+/// completing each one once the handler returns.
+pub unsafe fn dispatch<F: FnMut(Irq)>(mut handler: F) {
+    let mut c = controller();
+    while let Some(irq) = c.next() {
+        handler(irq);
+        c.complete(irq);
+    }
+}
+
+pub type Handler = fn(&mut ExceptionContext);
+
+static mut HANDLERS: [Option<Handler>; MAX_IRQ] = [None; MAX_IRQ];
+
+/// Registers `handler` for `irq` and enables it at the controller.
+///
+/// The handler runs with IRQs masked and must clear the interrupt at the
+/// peripheral before returning. Fails for an `irq` out of range.
+pub unsafe fn register(irq: Irq, handler: Handler) -> Result<(), ()> {
+    *HANDLERS.get_mut(irq.0 as usize).ok_or(())? = Some(handler);
+    enable(irq);
+    Ok(())
+}
+
+/// Disables `irq` and drops its handler. Fails for an `irq` out of range.
+pub unsafe fn unregister(irq: Irq) -> Result<(), ()> {
+    let slot = HANDLERS.get_mut(irq.0 as usize).ok_or(())?;
+    disable(irq);
+    *slot = None;
+    Ok(())
+}
+
+/// Unmasks IRQs on the calling core (clears DAIF.I).
+#[inline(always)]
+pub unsafe fn unmask() {
+    asm!("msr daifclr, #2");
+}
+
+/// Masks IRQs on the calling core (sets DAIF.I).
+#[inline(always)]
+pub unsafe fn mask() {
+    asm!("msr daifset, #2");
+}
+
+pub(crate) unsafe fn handle_irq(ctx: &mut ExceptionContext) {
+    dispatch(|irq| {
+        if let Some(handler) = HANDLERS.get(irq.0 as usize).copied().flatten() {
+            handler(ctx);
+        } else {
+            crate::eprintln!("Unhandled {:?}, disabling it", irq);
+            disable(irq);
+        }
+    });
+}
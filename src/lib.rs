@@ -1,21 +1,61 @@
 #![no_std]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(const_generics)]
 #![feature(const_fn)]
 #![feature(const_evaluatable_checked)]
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod armc;
 pub mod aux;
+pub mod aux_spi;
+pub mod board;
+pub mod bsc_slave;
+pub mod cache;
+pub mod clock_manager;
+pub mod cpu;
+pub mod dma;
+pub mod dtb;
+pub mod el;
+pub mod exception;
+pub mod gic;
 pub mod gpio;
+#[cfg(feature = "alloc")]
+pub mod heap;
+pub mod i2c;
+pub mod interrupt;
 mod macros;
 pub mod mailbox;
+pub mod mmu;
+pub mod pwm;
+#[cfg(feature = "rt")]
+pub mod rt;
+pub mod spi;
+pub mod sync;
 pub mod time;
 pub mod uart;
 
-#[cfg(feature = "raspberry-pi-4")]
-const PERIPHERALS_BASE: usize = 0xFE00_0000;
+#[cfg(feature = "rt")]
+pub use hal_macros::entry;
+
+/// ARM address of the `0x7E00_0000` peripheral bus window, 0 until set
+/// to something else than the board default
+static PERIPHERALS_BASE: AtomicUsize = AtomicUsize::new(0);
+
+#[inline(always)]
+pub fn peripherals_base() -> usize {
+    match PERIPHERALS_BASE.load(Ordering::Relaxed) {
+        0 => board::current().peripherals_base(),
+        base => base,
+    }
+}
 
-#[cfg(feature = "raspberry-pi-3")]
-const PERIPHERALS_BASE: usize = 0x3F00_0000;
+/// Moves every driver to the peripherals at `base`, e.g. a Pi 4 booted in
+/// low peripheral mode. Must happen before any peripheral is touched.
+pub unsafe fn set_peripherals_base(base: usize) {
+    PERIPHERALS_BASE.store(base, Ordering::Relaxed)
+}
 
 #[inline(always)]
 pub fn core_id() -> usize {
@@ -41,6 +81,19 @@ pub unsafe fn init() {
     uart::init_uart_0();
 }
 
+/// `init`, after taking the peripheral base from the device tree at `dtb`
+/// (`rt::dtb_ptr()` under `rt`). The blob is kept for `dtb::get`, it must
+/// stay untouched.
+pub unsafe fn init_from_dtb(dtb: usize) -> Result<(), dtb::Error> {
+    let fdt = dtb::Fdt::from_ptr(dtb as *const u8)?;
+    if let Some(base) = dtb::peripherals_base(&fdt) {
+        set_peripherals_base(base);
+    }
+    dtb::set(&fdt);
+    init();
+    Ok(())
+}
+
 pub fn firmware_version() -> u32 {
     mailbox::Message::new()
         .with(mailbox::tag::GetFirmwareVersion)
@@ -48,6 +101,14 @@ pub fn firmware_version() -> u32 {
         .unwrap()
 }
 
+/// Current rate of `clock` in Hz, 0 if the firmware does not know it
+pub fn clock_rate(clock: mailbox::Clock) -> u32 {
+    mailbox::Message::new()
+        .with(mailbox::tag::GetClockRate(clock))
+        .commit()
+        .unwrap()
+}
+
 pub fn memory() -> mailbox::tag_res::Ptr {
     mailbox::Message::new()
         .with(mailbox::tag::GetArmMemory)
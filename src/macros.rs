@@ -11,7 +11,6 @@ macro_rules! eprintln {
         $crate::eprint!("\r\n")
     };
     ($($args:tt)*) => {
-        $crate::uart::_print_internals(format_args!($($args)*));
-        $crate::eprint!("\r\n")
+        $crate::uart::_print_internals(format_args!("{}\r\n", format_args!($($args)*)))
     };
 }
@@ -1,7 +1,14 @@
 //use core::marker::PhantomData;
 use register::*;
 
-pub const MAILBOX_BASE: usize = super::PERIPHERALS_BASE + 0xB880;
+use super::cache::DmaBuffer;
+
+const MAILBOX_OFFSET: usize = 0xB880;
+
+#[inline(always)]
+pub fn mailbox_base() -> usize {
+    super::peripherals_base() + MAILBOX_OFFSET
+}
 
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
@@ -42,10 +49,8 @@ pub struct MailboxStruct {
     write: MailboxWrite,
 }
 
-#[allow(non_upper_case_globals)]
-const MailboxPtr: *mut MailboxStruct = MAILBOX_BASE as *mut MailboxStruct;
 unsafe fn mailbox<'a>() -> &'a mut MailboxStruct {
-    &mut *MailboxPtr
+    &mut *(mailbox_base() as *mut MailboxStruct)
 }
 
 #[derive(Clone, Copy)]
@@ -94,6 +99,25 @@ pub mod tag_res {
     pub struct Handle(pub(crate) u32);
 }
 
+/// Clocks known to the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Hevc = 11,
+    Emmc2 = 12,
+    M2mc = 13,
+    PixelBvb = 14,
+}
+
 pub mod tag {
     use super::*;
 
@@ -217,6 +241,32 @@ pub mod tag {
         }
     }
 
+    /// Rate in Hz, 0 when the clock does not exist
+    pub struct GetClockRate(pub Clock);
+    impl Tag for GetClockRate {
+        const ID: u32 = 0x30002;
+        const LEN: usize = 2;
+        type Res = (u32,);
+        fn serialize(self, buffer: &mut [u32]) {
+            buffer[0] = self.0 as u32;
+            buffer[1] = RESPONSE;
+        }
+        fn deserialize(from: &[u32]) -> Self::Res {
+            (from[1],)
+        }
+    }
+
+    /// Bit `n` set when DMA channel `n` is free for the ARM
+    pub struct GetDmaChannels;
+    impl Tag for GetDmaChannels {
+        const ID: u32 = 0x60001;
+        const LEN: usize = 1;
+        type Res = (u32,);
+        fn deserialize(from: &[u32]) -> Self::Res {
+            (from[0],)
+        }
+    }
+
     pub struct GetArmMemory;
     impl Tag for GetArmMemory {
         const ID: u32 = 0x10005;
@@ -343,9 +393,6 @@ pub trait Tag: Sized {
     fn deserialize(from: &[u32]) -> Self::Res;
 }
 
-#[repr(C, align(16))]
-struct Align16Buffer<T, const LEN: usize>([T; LEN]);
-
 pub struct Message<T> {
     tags: T,
 }
@@ -372,25 +419,28 @@ impl<O1, O2> Message<Tags<O1, O2>> {
         [u32; Tags::<O1, O2>::LEN + 3]: Sized,
         <Tags<O1, O2> as TagsHolder>::Res: Flatten,
     {
-        let mut buffer = Align16Buffer([0u32; Tags::<O1, O2>::LEN + 3]); // size + request code + tags + zero terminated
-        buffer.0[0] = (buffer.0.len() << 2) as u32; // Size
-                                                    //buffer.0[1] = 0; // Req code
+        let mut buffer = DmaBuffer::new([0u32; Tags::<O1, O2>::LEN + 3]); // size + request code + tags + zero terminated
+        buffer[0] = (buffer.len() << 2) as u32; // Size
+                                                //buffer[1] = 0; // Req code
 
-        let buffer_len = buffer.0.len();
-        self.tags.serialize(&mut buffer.0[2..buffer_len - 1]);
+        let buffer_len = buffer.len();
+        self.tags.serialize(&mut buffer[2..buffer_len - 1]);
 
-        //buffer.0[buffer.0.len() - 1] = 0; // Zero terminated
+        //buffer[buffer.len() - 1] = 0; // Zero terminated
 
-        let v = (buffer.0.as_ptr() as u32) >> 4;
+        // The VideoCore reads and writes the buffer behind the caches
+        buffer.prepare_for_device();
+        let v = (buffer.as_ptr() as u32) >> 4;
         let _ = Mailbox
             .write_message(Channel::TagsArmToVC, v)
             .read_message(Channel::TagsArmToVC);
+        buffer.complete_from_device();
 
-        if buffer.0[1] != 0x80000000 {
+        if buffer[1] != 0x80000000 {
             return Err(());
         }
 
-        let res = Tags::<O1, O2>::deserialize(&buffer.0[2..buffer_len - 1]);
+        let res = Tags::<O1, O2>::deserialize(&buffer[2..buffer_len - 1]);
 
         Ok(res.flatten())
     }
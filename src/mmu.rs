@@ -0,0 +1,411 @@
+//! Translation tables and MMU control.
+//!
+//! The address space is identity mapped with a 4 KiB granule and a 39-bit
+//! VA (walks start at level 1). The first 8 GiB are covered by 2 MiB
+//! blocks, which are split into 4 KiB pages on demand when a mapping does
+//! not line up with them. `init` maps the ARM memory reported by the
+//! firmware as normal cacheable memory and the peripheral window as
+//! device memory, then turns the MMU and caches on. Anything else stays
+//! unmapped until `map` is called, which also works with the MMU on.
+//!
+//! Tables are programmed for the exception level the caller runs at (EL1
+//! or EL2).
+
+const PAGE_SIZE: usize = 4096;
+const BLOCK_SIZE: usize = 2 * 1024 * 1024;
+const L1_SIZE: usize = 1024 * 1024 * 1024;
+const ENTRIES: usize = 512;
+
+/// GiBs covered by level 2 tables
+const L2_TABLES: usize = 8;
+/// Level 3 tables available to split blocks
+const L3_TABLES: usize = 32;
+
+pub const MAPPED_SIZE: usize = L2_TABLES * L1_SIZE;
+
+const DESC_VALID: u64 = 1 << 0;
+const DESC_TABLE: u64 = 1 << 1;
+const DESC_PAGE: u64 = 1 << 1;
+const DESC_AF: u64 = 1 << 10;
+const DESC_SH_INNER: u64 = 0b11 << 8;
+const DESC_AP_RO: u64 = 1 << 7;
+const DESC_PXN: u64 = 1 << 53;
+const DESC_UXN: u64 = 1 << 54;
+const DESC_ATTR_SHIFT: u64 = 2;
+const DESC_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+/// Attributes bits of a block or page descriptor
+const DESC_ATTRS_MASK: u64 = !DESC_ADDR_MASK & !0b11;
+
+/// Device-nGnRE, Normal WB RA/WA, Normal non-cacheable, Device-nGnRnE
+pub const DEFAULT_MAIR: u64 = 0x00_44_FF_04;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Device-nGnRE, index 0 of the default MAIR
+    Device,
+    /// Normal write-back cacheable, index 1 of the default MAIR
+    Normal,
+    /// Normal non-cacheable, index 2 of the default MAIR. Writes are
+    /// gathered, which is what framebuffers want.
+    WriteCombining,
+    /// Device-nGnRnE, index 3 of the default MAIR
+    StronglyOrdered,
+    /// Any index of a custom MAIR
+    Index(u8),
+}
+
+impl MemoryType {
+    fn index(self) -> u64 {
+        match self {
+            MemoryType::Device => 0,
+            MemoryType::Normal => 1,
+            MemoryType::WriteCombining => 2,
+            MemoryType::StronglyOrdered => 3,
+            MemoryType::Index(i) => (i & 0b111) as u64,
+        }
+    }
+
+    fn descriptor(self, read_only: bool) -> u64 {
+        let mut desc = DESC_VALID | DESC_AF | (self.index() << DESC_ATTR_SHIFT);
+        match self {
+            MemoryType::Device | MemoryType::StronglyOrdered => desc |= DESC_PXN | DESC_UXN,
+            _ => desc |= DESC_SH_INNER,
+        }
+        if read_only {
+            desc |= DESC_AP_RO;
+        }
+        desc
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// The region goes past `MAPPED_SIZE`
+    OutOfRange,
+    /// Addresses or size are not page aligned
+    Unaligned,
+    /// No level 3 table left to split a block
+    NoTableLeft,
+    /// Splitting would unmap, for the time of the split, the block holding
+    /// the running code or stack
+    LiveBlock,
+}
+
+/// Values programmed in `MAIR_ELx` and `TCR_ELx` by `init`.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub mair: u64,
+    pub tcr: u64,
+}
+
+impl Config {
+    /// TCR for the current exception level: T0SZ = 25, 4 KiB granule,
+    /// inner shareable write-back walks, 40-bit physical addresses.
+    pub fn default_tcr() -> u64 {
+        let t0sz = 25;
+        let walks = (0b01 << 8) | (0b01 << 10) | (0b11 << 12);
+        let pa_40_bits = 0b010;
+        match super::execption_level() {
+            // EPD1: no TTBR1 walks, IPS
+            1 => t0sz | walks | (1 << 23) | (pa_40_bits << 32),
+            // PS, RES1 bits
+            _ => t0sz | walks | (pa_40_bits << 16) | (1 << 23) | (1 << 31),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mair: DEFAULT_MAIR,
+            tcr: Self::default_tcr(),
+        }
+    }
+}
+
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES]);
+
+const EMPTY_TABLE: Table = Table([0; ENTRIES]);
+
+static mut L1: Table = EMPTY_TABLE;
+static mut L2: [Table; L2_TABLES] = [EMPTY_TABLE; L2_TABLES];
+static mut L3: [Table; L3_TABLES] = [EMPTY_TABLE; L3_TABLES];
+static mut L3_USED: usize = 0;
+/// Last configuration `enable` programmed, and at which exception level
+static mut ENABLED: Option<(Config, u8)> = None;
+
+#[inline(always)]
+fn barrier() {
+    unsafe { asm!("dsb ish", "isb") };
+}
+
+unsafe fn invalidate_tlb() {
+    asm!("dsb ishst");
+    match super::execption_level() {
+        1 => asm!("tlbi vmalle1is"),
+        _ => asm!("tlbi alle2is"),
+    }
+    barrier();
+}
+
+/// Writes a live descriptor following the break-before-make sequence.
+unsafe fn replace_entry(entry: &mut u64, desc: u64) {
+    if *entry & DESC_VALID != 0 {
+        core::ptr::write_volatile(entry, 0);
+        invalidate_tlb();
+    }
+    core::ptr::write_volatile(entry, desc);
+}
+
+unsafe fn l2_entry(addr: usize) -> &'static mut u64 {
+    &mut L2[addr / L1_SIZE].0[(addr % L1_SIZE) / BLOCK_SIZE]
+}
+
+/// Whether the 2 MiB block at `addr` holds the current stack or the code
+/// of the break-before-make sequence, which would fault while the block
+/// is unmapped.
+fn holds_running_code(addr: usize) -> bool {
+    let (pc, sp): (usize, usize);
+    unsafe { asm!("adr {}, .", "mov {}, sp", out(reg) pc, out(reg) sp) };
+    let block = addr / BLOCK_SIZE;
+    [pc, sp, replace_entry as usize, invalidate_tlb as usize]
+        .iter()
+        .any(|&a| a / BLOCK_SIZE == block)
+}
+
+/// Level 3 table covering the 2 MiB block at `addr`, splitting the block
+/// if needed. A live block is split with break-before-make, which fails
+/// with `MapError::LiveBlock` for the block the code runs from.
+unsafe fn l3_table(addr: usize) -> Result<&'static mut Table, MapError> {
+    let entry = l2_entry(addr);
+    if *entry & (DESC_VALID | DESC_TABLE) == DESC_VALID | DESC_TABLE {
+        let table = (*entry & DESC_ADDR_MASK) as *mut Table;
+        return Ok(&mut *table);
+    }
+
+    if *entry & DESC_VALID != 0 && is_enabled() && holds_running_code(addr) {
+        return Err(MapError::LiveBlock);
+    }
+    if L3_USED == L3_TABLES {
+        return Err(MapError::NoTableLeft);
+    }
+    let table = &mut L3[L3_USED];
+    L3_USED += 1;
+
+    let block_base = addr & !(BLOCK_SIZE - 1);
+    for (i, page) in table.0.iter_mut().enumerate() {
+        *page = if *entry & DESC_VALID != 0 {
+            (*entry & DESC_ATTRS_MASK)
+                | DESC_VALID
+                | DESC_PAGE
+                | (block_base + i * PAGE_SIZE) as u64
+        } else {
+            0
+        };
+    }
+    barrier();
+    replace_entry(entry, table as *mut Table as u64 | DESC_VALID | DESC_TABLE);
+    Ok(table)
+}
+
+/// Maps `[va, va + size)` to `[pa, pa + size)` as `ty`.
+///
+/// 2 MiB aligned parts use blocks, the rest 4 KiB pages. Can be called
+/// with the MMU on, e.g. to make a framebuffer write-combining.
+pub unsafe fn map_at(
+    va: usize,
+    pa: usize,
+    size: usize,
+    ty: MemoryType,
+    read_only: bool,
+) -> Result<(), MapError> {
+    if va % PAGE_SIZE != 0 || pa % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+        return Err(MapError::Unaligned);
+    }
+    if va.checked_add(size).map_or(true, |end| end > MAPPED_SIZE) {
+        return Err(MapError::OutOfRange);
+    }
+
+    let attrs = ty.descriptor(read_only);
+    let mut offset = 0;
+    while offset < size {
+        let (va, pa) = (va + offset, pa + offset);
+        let entry = l2_entry(va);
+        let is_table = *entry & (DESC_VALID | DESC_TABLE) == DESC_VALID | DESC_TABLE;
+        if !is_table && va % BLOCK_SIZE == 0 && pa % BLOCK_SIZE == 0 && size - offset >= BLOCK_SIZE
+        {
+            replace_entry(entry, attrs | pa as u64);
+            offset += BLOCK_SIZE;
+        } else {
+            let table = l3_table(va)?;
+            let page = &mut table.0[(va % BLOCK_SIZE) / PAGE_SIZE];
+            replace_entry(page, attrs | DESC_PAGE | pa as u64);
+            offset += PAGE_SIZE;
+        }
+    }
+    invalidate_tlb();
+    Ok(())
+}
+
+/// Identity maps `[addr, addr + size)` as `ty`.
+#[inline]
+pub unsafe fn map(addr: usize, size: usize, ty: MemoryType) -> Result<(), MapError> {
+    map_at(addr, addr, size, ty, false)
+}
+
+/// Removes the mapping of `[addr, addr + size)`, accesses will fault.
+pub unsafe fn unmap(addr: usize, size: usize) -> Result<(), MapError> {
+    if addr % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+        return Err(MapError::Unaligned);
+    }
+    if addr.checked_add(size).map_or(true, |end| end > MAPPED_SIZE) {
+        return Err(MapError::OutOfRange);
+    }
+
+    let mut offset = 0;
+    while offset < size {
+        let addr = addr + offset;
+        let entry = l2_entry(addr);
+        let is_table = *entry & (DESC_VALID | DESC_TABLE) == DESC_VALID | DESC_TABLE;
+        if !is_table && addr % BLOCK_SIZE == 0 && size - offset >= BLOCK_SIZE {
+            replace_entry(entry, 0);
+            offset += BLOCK_SIZE;
+        } else {
+            let table = l3_table(addr)?;
+            replace_entry(&mut table.0[(addr % BLOCK_SIZE) / PAGE_SIZE], 0);
+            offset += PAGE_SIZE;
+        }
+    }
+    invalidate_tlb();
+    Ok(())
+}
+
+#[inline]
+fn align_down(v: usize, align: usize) -> usize {
+    v & !(align - 1)
+}
+
+#[inline]
+fn align_up(v: usize, align: usize) -> usize {
+    align_down(v + align - 1, align)
+}
+
+/// Start and end of the device window mapped by `init`
+pub fn peripherals_window() -> (usize, usize) {
+    let base = super::peripherals_base();
+    (align_down(base, BLOCK_SIZE), base + 0x200_0000)
+}
+
+/// Builds the default tables and enables the MMU and caches on the
+/// calling core. Must be called once, with the MMU off.
+pub unsafe fn init(config: Config) -> Result<(), MapError> {
+    L3_USED = 0;
+    for table in L3.iter_mut() {
+        table.0 = [0; ENTRIES];
+    }
+    L1.0 = [0; ENTRIES];
+    for (i, table) in L2.iter_mut().enumerate() {
+        table.0 = [0; ENTRIES];
+        L1.0[i] = table as *mut Table as u64 | DESC_VALID | DESC_TABLE;
+    }
+
+    let memory = super::memory();
+    let start = align_down(memory.ptr as usize, PAGE_SIZE);
+    let end = align_up(memory.ptr as usize + memory.bytes, PAGE_SIZE);
+    map(start, end - start, MemoryType::Normal)?;
+
+    let (start, end) = peripherals_window();
+    map(start, end - start, MemoryType::Device)?;
+
+    enable(config);
+    Ok(())
+}
+
+/// Programs MAIR, TCR and TTBR0 and turns the MMU, data and instruction
+/// caches on. Also used by secondary cores to join the tables built by
+/// `init`.
+pub unsafe fn enable(config: Config) {
+    let ttbr = &L1 as *const Table as u64;
+    ENABLED = Some((config, super::execption_level()));
+    barrier();
+    match super::execption_level() {
+        1 => {
+            asm!(
+                "msr mair_el1, {}",
+                "msr tcr_el1, {}",
+                "msr ttbr0_el1, {}",
+                "isb",
+                "tlbi vmalle1",
+                "ic iallu",
+                "dsb nsh",
+                "isb",
+                in(reg) config.mair,
+                in(reg) config.tcr,
+                in(reg) ttbr,
+            );
+            let mut sctlr: u64;
+            asm!("mrs {}, sctlr_el1", out(reg) sctlr);
+            // M, C, I on, A (alignment checks) off
+            sctlr = (sctlr | 1 | (1 << 2) | (1 << 12)) & !(1 << 1);
+            asm!("msr sctlr_el1, {}", "isb", in(reg) sctlr);
+        }
+        _ => {
+            asm!(
+                "msr mair_el2, {}",
+                "msr tcr_el2, {}",
+                "msr ttbr0_el2, {}",
+                "isb",
+                "tlbi alle2",
+                "ic iallu",
+                "dsb nsh",
+                "isb",
+                in(reg) config.mair,
+                in(reg) config.tcr,
+                in(reg) ttbr,
+            );
+            let mut sctlr: u64;
+            asm!("mrs {}, sctlr_el2", out(reg) sctlr);
+            sctlr = (sctlr | 1 | (1 << 2) | (1 << 12)) & !(1 << 1);
+            asm!("msr sctlr_el2, {}", "isb", in(reg) sctlr);
+        }
+    }
+}
+
+/// Whether `enable` ran, so that secondary cores can join its tables
+pub(crate) fn is_shared() -> bool {
+    unsafe { ENABLED.is_some() }
+}
+
+/// Enables the MMU on a secondary core at EL1 with the tables and memory
+/// attributes of the core that ran `init`. The TCR is kept if that core
+/// ran at EL1 as well, otherwise the EL1 default is used. Returns false if
+/// no core enabled the MMU yet.
+pub(crate) unsafe fn enable_secondary() -> bool {
+    match ENABLED {
+        Some((config, el)) => {
+            let tcr = if el == super::execption_level() {
+                config.tcr
+            } else {
+                Config::default_tcr()
+            };
+            enable(Config {
+                mair: config.mair,
+                tcr,
+            });
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn is_enabled() -> bool {
+    let sctlr: u64;
+    unsafe {
+        match super::execption_level() {
+            1 => asm!("mrs {}, sctlr_el1", out(reg) sctlr),
+            _ => asm!("mrs {}, sctlr_el2", out(reg) sctlr),
+        }
+    }
+    sctlr & 1 != 0
+}
@@ -0,0 +1,329 @@
+//! PWM controllers, two channels each.
+//!
+//! PWM0 exists on both boards, PWM1 on the BCM2711 only. Both are fed by
+//! the single PWM clock of the clock manager, set up with `set_clock`
+//! before enabling a channel.
+//!
+//! A channel outputs `data` high bits out of every `range`: spread evenly
+//! over the period in PWM mode, as one pulse in mark-space mode (what
+//! servos and most fans expect). In serialiser mode it shifts `data`, or
+//! the shared FIFO, out MSB first, `range` bits per word.
+
+use core::convert::Infallible;
+
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+use register::*;
+
+use super::{
+    board::{self, Board},
+    clock_manager::{self, Generator, Source},
+    gpio::{Function, GPIO},
+};
+
+const PWM0_OFFSET: usize = 0x20_C000;
+const PWM1_OFFSET: usize = 0x20_C800;
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct PWMStruct {
+    pub CTL: Register<u32>,
+    pub STA: Register<u32>,
+    pub DMAC: Register<u32>,
+    _reserved0: u32,
+    pub RNG1: Register<u32>,
+    pub DAT1: Register<u32>,
+    /// Shared by both channels
+    pub FIF1: Register<u32>,
+    _reserved1: u32,
+    pub RNG2: Register<u32>,
+    pub DAT2: Register<u32>,
+}
+
+// CTL, per channel and 8 bits apart
+const CTL_PWEN: u32 = 1 << 0;
+const CTL_MODE: u32 = 1 << 1;
+const CTL_RPTL: u32 = 1 << 2;
+const CTL_SBIT: u32 = 1 << 3;
+const CTL_POLA: u32 = 1 << 4;
+const CTL_USEF: u32 = 1 << 5;
+const CTL_CLRF: u32 = 1 << 6;
+const CTL_MSEN: u32 = 1 << 7;
+
+// STA
+const STA_FULL: u32 = 0;
+const STA_EMPT: u32 = 1;
+const STA_ERRORS: u32 = 0x1FC;
+
+// DMAC
+const DMAC_ENAB: u32 = 1 << 31;
+const DMAC_PANIC: u32 = 8;
+
+const FIFO_OFFSET: usize = 0x18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    Pwm0,
+    /// BCM2711 only
+    Pwm1,
+}
+
+impl Controller {
+    fn offset(self) -> usize {
+        match self {
+            Controller::Pwm0 => PWM0_OFFSET,
+            Controller::Pwm1 => PWM1_OFFSET,
+        }
+    }
+
+    fn regs<'a>(self) -> &'a mut PWMStruct {
+        let base = super::peripherals_base() + self.offset();
+        unsafe { &mut *(base as *mut PWMStruct) }
+    }
+
+    /// Bus address of the FIFO, for a DMA destination
+    pub fn fifo_bus_address(self) -> u32 {
+        (0x7E00_0000 + self.offset() + FIFO_OFFSET) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// High bits spread over the period
+    Pwm,
+    /// One high pulse of `data` out of `range` clock cycles
+    MarkSpace,
+    /// `data` or the FIFO shifted out, `range` bits per word
+    Serialiser,
+}
+
+/// Starts the PWM clock at `hz` or the next rate above it, derived from
+/// `source` with an integer divider. Stops both controllers' output for
+/// the time of the change. Fails for a source of unknown frequency.
+pub unsafe fn set_clock(source: Source, hz: u32) -> Result<u32, ()> {
+    let rate = source.frequency().ok_or(())?;
+    let divi = (rate / hz.max(1)).max(2);
+    clock_manager::start_integer(Generator::Pwm.regs(), source, divi);
+    Ok(rate / divi.min(0xFFF))
+}
+
+/// Starts the PWM clock from the oscillator, see `set_clock`.
+pub unsafe fn init_clock(hz: u32) -> Result<u32, ()> {
+    set_clock(Source::Oscillator, hz)
+}
+
+pub struct Channel {
+    controller: Controller,
+    index: usize,
+}
+
+impl Channel {
+    /// Channel 0 or 1 of `controller`, fails for PWM1 on a Pi 3. Only one
+    /// `Channel` must exist per channel.
+    pub unsafe fn new(controller: Controller, index: usize) -> Result<Self, ()> {
+        if index > 1 || controller == Controller::Pwm1 && board::current() == Board::RaspberryPi3 {
+            return Err(());
+        }
+        Ok(Channel { controller, index })
+    }
+
+    #[inline]
+    fn shift(&self) -> u32 {
+        8 * self.index as u32
+    }
+
+    fn rng<'a>(&self) -> &'a mut Register<u32> {
+        let regs = self.controller.regs();
+        if self.index == 0 {
+            &mut regs.RNG1
+        } else {
+            &mut regs.RNG2
+        }
+    }
+
+    fn dat<'a>(&self) -> &'a mut Register<u32> {
+        let regs = self.controller.regs();
+        if self.index == 0 {
+            &mut regs.DAT1
+        } else {
+            &mut regs.DAT2
+        }
+    }
+
+    fn update_ctl(&mut self, clear: u32, set: u32) {
+        let shift = self.shift();
+        let regs = self.controller.regs();
+        unsafe {
+            let ctl = regs.CTL.read() & !(clear << shift) & !CTL_CLRF;
+            regs.CTL.write(ctl | set << shift);
+        }
+    }
+
+    /// Routes the channel to `pin`, one of GPIO 12/13 (ALT0), 18/19
+    /// (ALT5) or 45 (ALT0, channel 1) for PWM0, and 40/41 (ALT0) for PWM0
+    /// on a Pi 3 or PWM1 on a Pi 4. Fails for any other pin.
+    pub unsafe fn init_pin(&mut self, pin: usize) -> Result<&mut Self, ()> {
+        let audio = match board::current() {
+            Board::RaspberryPi3 => Controller::Pwm0,
+            Board::RaspberryPi4 => Controller::Pwm1,
+        };
+        let function = match (self.controller, self.index, pin) {
+            (Controller::Pwm0, 0, 12) | (Controller::Pwm0, 1, 13) | (Controller::Pwm0, 1, 45) => {
+                Function::Alternate0
+            }
+            (Controller::Pwm0, 0, 18) | (Controller::Pwm0, 1, 19) => Function::Alternate5,
+            (c, 0, 40) | (c, 1, 41) if c == audio => Function::Alternate0,
+            _ => return Err(()),
+        };
+        GPIO(pin).set_function(function);
+        Ok(self)
+    }
+
+    pub fn enable(&mut self) -> &mut Self {
+        self.update_ctl(0, CTL_PWEN);
+        self
+    }
+
+    pub fn disable(&mut self) -> &mut Self {
+        self.update_ctl(CTL_PWEN, 0);
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        unsafe { self.controller.regs().CTL.read() & CTL_PWEN << self.shift() != 0 }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) -> &mut Self {
+        let set = match mode {
+            Mode::Pwm => 0,
+            Mode::MarkSpace => CTL_MSEN,
+            Mode::Serialiser => CTL_MODE,
+        };
+        self.update_ctl(CTL_MSEN | CTL_MODE, set);
+        self
+    }
+
+    /// Inverts the output
+    pub fn set_polarity(&mut self, inverted: bool) -> &mut Self {
+        self.update_ctl(CTL_POLA, if inverted { CTL_POLA } else { 0 });
+        self
+    }
+
+    /// Level output while the channel has nothing to send
+    pub fn set_silence_bit(&mut self, high: bool) -> &mut Self {
+        self.update_ctl(CTL_SBIT, if high { CTL_SBIT } else { 0 });
+        self
+    }
+
+    /// Feeds the channel from the FIFO instead of `data`
+    pub fn use_fifo(&mut self, fifo: bool) -> &mut Self {
+        self.update_ctl(CTL_USEF, if fifo { CTL_USEF } else { 0 });
+        self
+    }
+
+    /// Repeats the last FIFO word once the FIFO runs empty
+    pub fn set_repeat_last(&mut self, repeat: bool) -> &mut Self {
+        self.update_ctl(CTL_RPTL, if repeat { CTL_RPTL } else { 0 });
+        self
+    }
+
+    /// Period in PWM clock cycles, or bits per word in serialiser mode
+    pub fn set_range(&mut self, range: u32) -> &mut Self {
+        unsafe { self.rng().write(range) };
+        self
+    }
+
+    pub fn range(&self) -> u32 {
+        unsafe { self.rng().read() }
+    }
+
+    /// High cycles per period, or the word shifted out in serialiser mode
+    pub fn set_data(&mut self, data: u32) -> &mut Self {
+        unsafe { self.dat().write(data) };
+        self
+    }
+
+    pub fn data(&self) -> u32 {
+        unsafe { self.dat().read() }
+    }
+
+    /// Sets the period in clock cycles and the duty cycle in `per_mille`
+    pub fn set_period(&mut self, range: u32, per_mille: u32) -> &mut Self {
+        let data = (range as u64 * per_mille.min(1000) as u64 / 1000) as u32;
+        self.set_range(range).set_data(data)
+    }
+
+    /// Queues a word in the FIFO shared by both channels of the
+    /// controller, which takes turns when both use it. Fails with the
+    /// FIFO full.
+    pub fn write_fifo(&mut self, word: u32) -> Result<(), ()> {
+        let regs = self.controller.regs();
+        unsafe {
+            if regs.STA.get(STA_FULL) {
+                Err(())
+            } else {
+                regs.FIF1.write(word);
+                Ok(())
+            }
+        }
+    }
+
+    /// Queues as many `words` as fit, returns how many.
+    pub fn write_fifo_slice(&mut self, words: &[u32]) -> usize {
+        words
+            .iter()
+            .take_while(|w| self.write_fifo(**w).is_ok())
+            .count()
+    }
+
+    pub fn is_fifo_empty(&self) -> bool {
+        unsafe { self.controller.regs().STA.get(STA_EMPT) }
+    }
+
+    /// Drops the content of the shared FIFO
+    pub fn clear_fifo(&mut self) -> &mut Self {
+        let regs = self.controller.regs();
+        unsafe {
+            let ctl = regs.CTL.read();
+            regs.CTL.write(ctl | CTL_CLRF);
+        }
+        self
+    }
+
+    /// Clears the sticky FIFO, gap and bus errors of the controller
+    pub fn clear_errors(&mut self) -> &mut Self {
+        unsafe { self.controller.regs().STA.write(STA_ERRORS) };
+        self
+    }
+
+    /// Lets a DMA channel feed the FIFO, requesting data below
+    /// `threshold` entries and raising the panic signal below `panic`.
+    pub fn enable_dma(&mut self, threshold: u8, panic: u8) -> &mut Self {
+        let value = DMAC_ENAB | (panic as u32) << DMAC_PANIC | threshold as u32;
+        unsafe { self.controller.regs().DMAC.write(value) };
+        self
+    }
+
+    pub fn disable_dma(&mut self) -> &mut Self {
+        unsafe { self.controller.regs().DMAC.write(0) };
+        self
+    }
+}
+
+impl ErrorType for Channel {
+    type Error = Infallible;
+}
+
+/// The duty cycle is a share of `range`, which is clamped to 16 bits for
+/// `max_duty_cycle`.
+impl SetDutyCycle for Channel {
+    fn max_duty_cycle(&self) -> u16 {
+        self.range().min(u16::MAX as u32).max(1) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+        let max = self.max_duty_cycle() as u64;
+        let data = self.range() as u64 * (duty as u64).min(max) / max;
+        self.set_data(data as u32);
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//! Runtime startup, enabled by the `rt` feature.
+//!
+//! `_start` is placed at the load address by the bundled `link.ld`. Every
+//! core gets its own stack, core 0 then clears `.bss`, copies `.data`,
+//! drops to EL1 if started above it and jumps to the function marked with
+//! `#[hal::entry]`. The other cores wait on the spin table until
+//! `cpu::start_core` releases them. This works the same whether the
+//! firmware starts every core at `_start` (Pi 3) or holds the secondary
+//! ones in its own spin table (Pi 4).
+
+global_asm!(
+    r#"
+.section .text.boot, "ax"
+.global _start
+_start:
+    mrs x1, mpidr_el1
+    and x1, x1, #3
+    ldr x2, =__stack_top
+    ldr x3, =__core_stack_size
+    msub x2, x1, x3, x2
+    mov sp, x2
+    cbnz x1, 4f
+
+    ldr x1, =__bss_start
+    ldr x2, =__bss_end
+1:  cmp x1, x2
+    b.hs 2f
+    stp xzr, xzr, [x1], #16
+    b 1b
+
+2:  ldr x1, =__data_lma
+    ldr x2, =__data_start
+    ldr x3, =__data_end
+    cmp x1, x2
+    b.eq 4f
+3:  cmp x2, x3
+    b.hs 4f
+    ldr x4, [x1], #8
+    str x4, [x2], #8
+    b 3b
+
+4:  b __hal_rt_start
+"#
+);
+
+extern "Rust" {
+    fn __hal_main() -> !;
+}
+
+static mut DTB: usize = 0;
+
+#[no_mangle]
+unsafe extern "C" fn __hal_rt_start(dtb: usize) -> ! {
+    if super::core_id() != 0 {
+        super::cpu::wait_for_release()
+    }
+    if super::execption_level() > 1 {
+        let sp: usize;
+        asm!("mov {}, sp", out(reg) sp);
+        super::el::enter_el1(start_el1, sp, dtb)
+    }
+    start_el1(dtb)
+}
+
+extern "C" fn start_el1(dtb: usize) -> ! {
+    unsafe {
+        DTB = dtb;
+        __hal_main()
+    }
+}
+
+/// Address of the device tree blob the firmware passed in `x0`, 0 if none.
+pub fn dtb_ptr() -> usize {
+    unsafe { DTB }
+}
+
+/// Puts the calling core to sleep for good.
+pub fn park() -> ! {
+    loop {
+        unsafe { asm!("wfe") };
+    }
+}
@@ -0,0 +1,444 @@
+//! SPI0 and, on the BCM2711, SPI3 to SPI6.
+//!
+//! All five are the same controller: 64-byte FIFOs, two or three hardware
+//! chip selects asserted while a transfer is active (`CS.TA`), LoSSI mode
+//! and DMA requests. The clock is `core_clock / CDIV` with an even `CDIV`.
+//!
+//! `Spi` implements `SpiBus`, which leaves every hardware chip select
+//! inactive as the `embedded-hal` contract requires, for use under a
+//! `SpiDevice` wrapper driving a GPIO chip select. It also implements
+//! `SpiDevice` itself, asserting the configured hardware chip select for
+//! the whole transaction. With a GPIO chip select, leave the CE pins
+//! alone (`init_pins(false)`).
+
+use core::convert::Infallible;
+
+use embedded_hal::spi::{ErrorType, Mode, Operation, Phase, Polarity, SpiBus, SpiDevice};
+use register::*;
+
+use super::{
+    board::{self, Board},
+    gpio::{Function, GPIO},
+    time,
+};
+
+pub use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+
+/// Bus address of the SPI0 registers, DMA engines use it for the FIFO
+const SPI0_BUS_ADDRESS: u32 = 0x7E20_4000;
+const SPI0_OFFSET: usize = 0x20_4000;
+
+const FIFO_SIZE: usize = 64;
+
+// CS
+const CS_CS: u32 = 0;
+const CS_CPHA: u32 = 1 << 2;
+const CS_CPOL: u32 = 1 << 3;
+const CS_CLEAR_TX: u32 = 1 << 4;
+const CS_CLEAR_RX: u32 = 1 << 5;
+const CS_CSPOL: u32 = 1 << 6;
+const CS_TA: u32 = 1 << 7;
+const CS_DMAEN: u32 = 1 << 8;
+const CS_ADCS: u32 = 1 << 11;
+const CS_LEN: u32 = 1 << 13;
+const CS_DONE: u32 = 16;
+const CS_RXD: u32 = 17;
+const CS_TXD: u32 = 18;
+const CS_CSPOL0: u32 = 21;
+
+#[allow(non_snake_case)]
+#[repr(packed)]
+pub struct SpiStruct {
+    pub CS: Register<u32>,
+    pub FIFO: Register<u32>,
+    pub CLK: Register<u32>,
+    pub DLEN: Register<u32>,
+    pub LTOH: Register<u32>,
+    pub DC: Register<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Spi0,
+    /// BCM2711 only, like the following ones
+    Spi3,
+    Spi4,
+    Spi5,
+    Spi6,
+}
+
+impl Bus {
+    /// Offset from the SPI0 registers
+    fn offset(self) -> usize {
+        match self {
+            Bus::Spi0 => 0,
+            Bus::Spi3 => 0x600,
+            Bus::Spi4 => 0x800,
+            Bus::Spi5 => 0xA00,
+            Bus::Spi6 => 0xC00,
+        }
+    }
+
+    /// CE1, CE0, MISO, MOSI and SCLK
+    fn pins(self) -> [(usize, Function); 5] {
+        use Function::*;
+        match self {
+            Bus::Spi0 => [
+                (7, Alternate0),
+                (8, Alternate0),
+                (9, Alternate0),
+                (10, Alternate0),
+                (11, Alternate0),
+            ],
+            Bus::Spi3 => [
+                (24, Alternate5),
+                (0, Alternate3),
+                (1, Alternate3),
+                (2, Alternate3),
+                (3, Alternate3),
+            ],
+            Bus::Spi4 => [
+                (25, Alternate5),
+                (4, Alternate3),
+                (5, Alternate3),
+                (6, Alternate3),
+                (7, Alternate3),
+            ],
+            Bus::Spi5 => [
+                (26, Alternate5),
+                (12, Alternate3),
+                (13, Alternate3),
+                (14, Alternate3),
+                (15, Alternate3),
+            ],
+            Bus::Spi6 => [
+                (27, Alternate5),
+                (18, Alternate3),
+                (19, Alternate3),
+                (20, Alternate3),
+                (21, Alternate3),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipSelect {
+    Cs0 = 0,
+    Cs1 = 1,
+    /// Not routed out on the Pi boards
+    Cs2 = 2,
+    /// No hardware chip select, for GPIO-driven ones
+    None = 3,
+}
+
+pub struct Spi {
+    bus: Bus,
+    /// `CS` configuration bits, `TA` excluded
+    cs: u32,
+    cdiv: u32,
+    clock: u32,
+}
+
+impl Spi {
+    /// Fails for SPI3–6 on a Pi 3. Only one `Spi` must exist per
+    /// controller.
+    pub unsafe fn new(bus: Bus) -> Result<Self, ()> {
+        if bus != Bus::Spi0 && board::current() == Board::RaspberryPi3 {
+            return Err(());
+        }
+        let mut spi = Spi {
+            bus,
+            cs: ChipSelect::Cs0 as u32,
+            cdiv: 0,
+            clock: board::current().core_clock(),
+        };
+        spi.regs().CS.write(CS_CLEAR_TX | CS_CLEAR_RX);
+        spi.set_frequency(1_000_000);
+        Ok(spi)
+    }
+
+    #[inline]
+    fn regs(&mut self) -> &mut SpiStruct {
+        let base = super::peripherals_base() + SPI0_OFFSET + self.bus.offset();
+        unsafe { &mut *(base as *mut SpiStruct) }
+    }
+
+    /// Muxes MISO, MOSI and SCLK, and CE0/CE1 if `with_cs`.
+    pub unsafe fn init_pins(&mut self, with_cs: bool) -> &mut Self {
+        let pins = self.bus.pins();
+        let skip = if with_cs { 0 } else { 2 };
+        for &(pin, function) in &pins[skip..] {
+            GPIO(pin).set_function(function);
+        }
+        self
+    }
+
+    fn apply(&mut self) {
+        let cs = self.cs;
+        unsafe { self.regs().CS.write(cs) };
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) -> &mut Self {
+        self.cs &= !(CS_CPOL | CS_CPHA);
+        if mode.polarity == Polarity::IdleHigh {
+            self.cs |= CS_CPOL;
+        }
+        if mode.phase == Phase::CaptureOnSecondTransition {
+            self.cs |= CS_CPHA;
+        }
+        self.apply();
+        self
+    }
+
+    /// Sets the fastest clock not above `hz`. See `frequency` for the
+    /// resulting rate.
+    pub fn set_frequency(&mut self, hz: u32) -> &mut Self {
+        self.clock = board::core_clock();
+        let cdiv = (self.clock + hz.max(1) - 1) / hz.max(1);
+        let cdiv = (cdiv + 1) & !1;
+        // 0 stands for 65536
+        self.cdiv = if cdiv > 0xFFFE { 0 } else { cdiv.max(2) };
+        let cdiv = self.cdiv;
+        unsafe { self.regs().CLK.write(cdiv) };
+        self
+    }
+
+    pub fn frequency(&self) -> u32 {
+        match self.cdiv {
+            0 => self.clock / 0x1_0000,
+            cdiv => self.clock / cdiv,
+        }
+    }
+
+    pub fn set_chip_select(&mut self, cs: ChipSelect) -> &mut Self {
+        self.cs = (self.cs & !(0b11 << CS_CS)) | (cs as u32) << CS_CS;
+        self.apply();
+        self
+    }
+
+    /// Sets the active level of `cs`, low by default.
+    pub fn set_cs_polarity(&mut self, cs: ChipSelect, active_high: bool) -> &mut Self {
+        if cs != ChipSelect::None {
+            let bit = 1 << (CS_CSPOL0 + cs as u32);
+            if active_high {
+                self.cs |= bit;
+            } else {
+                self.cs &= !bit;
+            }
+            // Also drives the lines while idle
+            if self.cs & (0b111 << CS_CSPOL0) != 0 {
+                self.cs |= CS_CSPOL;
+            } else {
+                self.cs &= !CS_CSPOL;
+            }
+        }
+        self.apply();
+        self
+    }
+
+    /// LoSSI mode: FIFO writes are 9 bits, bit 8 telling data from
+    /// command. See `write_lossi`.
+    pub fn set_lossi(&mut self, enable: bool) -> &mut Self {
+        if enable {
+            self.cs |= CS_LEN;
+        } else {
+            self.cs &= !CS_LEN;
+        }
+        self.apply();
+        self
+    }
+
+    /// Asserts CS and starts a transfer, clearing both FIFOs.
+    pub fn begin(&mut self) -> &mut Self {
+        let cs = self.cs;
+        unsafe { self.regs().CS.write(cs | CS_CLEAR_TX | CS_CLEAR_RX | CS_TA) };
+        self
+    }
+
+    /// Starts a transfer with no chip select asserted, for `SpiBus`.
+    fn begin_unselected(&mut self) -> &mut Self {
+        let cs = self.cs & !(0b11 << CS_CS) | (ChipSelect::None as u32) << CS_CS;
+        unsafe { self.regs().CS.write(cs | CS_CLEAR_TX | CS_CLEAR_RX | CS_TA) };
+        self
+    }
+
+    /// Waits for the last bits to go out and releases CS.
+    pub fn end(&mut self) -> &mut Self {
+        let cs = self.cs;
+        let regs = self.regs();
+        unsafe {
+            while !regs.CS.get(CS_DONE) {}
+            regs.CS.write(cs);
+        }
+        self
+    }
+
+    /// Polled exchange of a single byte, between `begin` and `end`.
+    pub fn transfer_byte(&mut self, byte: u8) -> u8 {
+        let regs = self.regs();
+        unsafe {
+            while !regs.CS.get(CS_TXD) {}
+            regs.FIFO.write(byte as u32);
+            while !regs.CS.get(CS_RXD) {}
+            regs.FIFO.read() as u8
+        }
+    }
+
+    /// Sends a LoSSI command (`data` false) or parameter byte, between
+    /// `begin` and `end`. Received bits are dropped.
+    pub fn write_lossi(&mut self, byte: u8, data: bool) {
+        let regs = self.regs();
+        unsafe {
+            while !regs.CS.get(CS_TXD) {}
+            regs.FIFO.write((data as u32) << 8 | byte as u32);
+            while regs.CS.get(CS_RXD) {
+                regs.FIFO.read();
+            }
+        }
+    }
+
+    /// FIFO-driven exchange of `len` bytes, between `begin` and `end`.
+    fn run<T, R>(&mut self, len: usize, mut tx: T, mut rx: R)
+    where
+        T: FnMut(usize) -> u8,
+        R: FnMut(usize, u8),
+    {
+        let regs = self.regs();
+        let mut sent = 0;
+        let mut received = 0;
+
+        while received < len {
+            while sent < len && sent - received < FIFO_SIZE && unsafe { regs.CS.get(CS_TXD) } {
+                unsafe { regs.FIFO.write(tx(sent) as u32) };
+                sent += 1;
+            }
+            while received < sent && unsafe { regs.CS.get(CS_RXD) } {
+                rx(received, unsafe { regs.FIFO.read() } as u8);
+                received += 1;
+            }
+        }
+    }
+
+    /// Bus address of the FIFO, for the DMA engines
+    pub fn fifo_bus_address(&self) -> u32 {
+        SPI0_BUS_ADDRESS + self.bus.offset() as u32 + 4
+    }
+
+    /// FIFO levels raising the DMA requests and panic signals, see `DC`.
+    pub fn set_dma_thresholds(
+        &mut self,
+        tx_request: u8,
+        tx_panic: u8,
+        rx_request: u8,
+        rx_panic: u8,
+    ) -> &mut Self {
+        let dc = (rx_panic as u32) << 24
+            | (rx_request as u32) << 16
+            | (tx_panic as u32) << 8
+            | tx_request as u32;
+        unsafe { self.regs().DC.write(dc) };
+        self
+    }
+
+    /// Starts a `len` bytes transfer fed by DMA: the TX channel writes the
+    /// FIFO and the RX channel drains it, paced by the SPI DREQs. CS is
+    /// released by the controller once `len` bytes are done; call `end`
+    /// after the RX channel completes. On SPI0 the DREQs are
+    /// `dma::Dreq::SPI0_TX` and `SPI0_RX`.
+    pub fn begin_dma(&mut self, len: u16) -> &mut Self {
+        let cs = self.cs;
+        let regs = self.regs();
+        unsafe {
+            regs.DLEN.write(len as u32);
+            regs.CS
+                .write(cs | CS_CLEAR_TX | CS_CLEAR_RX | CS_DMAEN | CS_ADCS | CS_TA);
+        }
+        self
+    }
+}
+
+impl ErrorType for Spi {
+    type Error = Infallible;
+}
+
+impl Spi {
+    fn read_words(&mut self, words: &mut [u8]) {
+        self.run(words.len(), |_| 0, |i, b| words[i] = b);
+    }
+
+    fn write_words(&mut self, words: &[u8]) {
+        self.run(words.len(), |i| words[i], |_, _| ());
+    }
+
+    fn transfer_words(&mut self, read: &mut [u8], write: &[u8]) {
+        let len = read.len().max(write.len());
+        self.run(
+            len,
+            |i| write.get(i).copied().unwrap_or(0),
+            |i, b| {
+                if let Some(r) = read.get_mut(i) {
+                    *r = b
+                }
+            },
+        );
+    }
+
+    fn transfer_words_in_place(&mut self, words: &mut [u8]) {
+        // Byte `i` is always sent before it is received
+        let ptr = words.as_mut_ptr();
+        self.run(
+            words.len(),
+            |i| unsafe { *ptr.add(i) },
+            |i, b| unsafe { *ptr.add(i) = b },
+        );
+    }
+}
+
+impl SpiBus for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        self.begin_unselected().read_words(words);
+        self.end();
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        self.begin_unselected().write_words(words);
+        self.end();
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        self.begin_unselected().transfer_words(read, write);
+        self.end();
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        self.begin_unselected().transfer_words_in_place(words);
+        self.end();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        // Every call already waits for DONE
+        Ok(())
+    }
+}
+
+impl SpiDevice for Spi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        self.begin();
+        for op in operations {
+            match op {
+                Operation::Read(words) => self.read_words(words),
+                Operation::Write(words) => self.write_words(words),
+                Operation::Transfer(read, write) => self.transfer_words(read, write),
+                Operation::TransferInPlace(words) => self.transfer_words_in_place(words),
+                Operation::DelayNs(ns) => unsafe { time::wait_ns(*ns as u64) },
+            }
+        }
+        self.end();
+        Ok(())
+    }
+}
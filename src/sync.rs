@@ -0,0 +1,208 @@
+//! Spinlocks built on load/store-exclusive.
+//!
+//! Exclusive accesses only succeed on cacheable memory. Until the MMU and
+//! data cache are on, all memory is Device and a store-exclusive never
+//! succeeds, so the locks below are bypassed: they only start excluding
+//! once `SCTLR.M` and `SCTLR.C` are set. Secondary cores must not be
+//! started before that, `cpu::start_core` refuses to, and each of them
+//! turns its own MMU on before running its entry.
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether the MMU and data cache are on, so that exclusives work
+pub(crate) fn exclusives_available() -> bool {
+    let sctlr: u64;
+    unsafe {
+        match super::execption_level() {
+            1 => asm!("mrs {}, sctlr_el1", out(reg) sctlr),
+            2 => asm!("mrs {}, sctlr_el2", out(reg) sctlr),
+            _ => asm!("mrs {}, sctlr_el3", out(reg) sctlr),
+        }
+    }
+    // M and C
+    sctlr & 0b101 == 0b101
+}
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn acquire(&self) -> bool {
+        if !exclusives_available() {
+            return false;
+        }
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Woken up by the `sev` in `release`
+            while self.locked.load(Ordering::Relaxed) {
+                unsafe { asm!("wfe") };
+            }
+        }
+        true
+    }
+
+    fn try_acquire(&self) -> Option<bool> {
+        if !exclusives_available() {
+            return Some(false);
+        }
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| true)
+    }
+
+    fn release(&self) {
+        self.locked.store(false, Ordering::Release);
+        unsafe { asm!("dsb ish", "sev") };
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        SpinLockGuard {
+            acquired: self.acquire(),
+            lock: self,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.try_acquire().map(|acquired| SpinLockGuard {
+            acquired,
+            lock: self,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Releases the lock whoever holds it.
+    ///
+    /// Only meant for fatal paths (panics, unrecoverable exceptions) where
+    /// the holder may never run again, e.g. to get a last message out.
+    pub unsafe fn force_unlock(&self) {
+        self.release()
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    acquired: bool,
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.acquired {
+            self.lock.release()
+        }
+    }
+}
+
+/// Spinlock that also masks IRQs and FIQs on the holding core, so it can
+/// be shared with interrupt handlers without deadlocking.
+pub struct IrqSpinLock<T>(SpinLock<T>);
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        IrqSpinLock(SpinLock::new(data))
+    }
+
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let daif = mask_interrupts();
+        IrqSpinLockGuard {
+            guard: core::mem::ManuallyDrop::new(self.0.lock()),
+            daif,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let daif = mask_interrupts();
+        match self.0.try_lock() {
+            Some(guard) => Some(IrqSpinLockGuard {
+                guard: core::mem::ManuallyDrop::new(guard),
+                daif,
+            }),
+            None => {
+                restore_interrupts(daif);
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.0.is_locked()
+    }
+
+    /// See [`SpinLock::force_unlock`].
+    pub unsafe fn force_unlock(&self) {
+        self.0.force_unlock()
+    }
+}
+
+pub struct IrqSpinLockGuard<'a, T> {
+    guard: core::mem::ManuallyDrop<SpinLockGuard<'a, T>>,
+    daif: u64,
+}
+
+impl<'a, T> Deref for IrqSpinLockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for IrqSpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, T> Drop for IrqSpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        // Release before unmasking, an interrupt could try to take it
+        unsafe { core::mem::ManuallyDrop::drop(&mut self.guard) };
+        restore_interrupts(self.daif);
+    }
+}
+
+#[inline(always)]
+fn mask_interrupts() -> u64 {
+    let daif: u64;
+    unsafe { asm!("mrs {}, daif", "msr daifset, #3", out(reg) daif) };
+    daif
+}
+
+#[inline(always)]
+fn restore_interrupts(daif: u64) {
+    unsafe { asm!("msr daif, {}", in(reg) daif) };
+}
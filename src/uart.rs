@@ -1,27 +1,38 @@
 use core::fmt::Write;
 
 use super::{
-    aux::aux,
-    gpio::{Function, GPIOTuple, Resistor, GPIO},
+    aux::{aux, ENABLE_MINI_UART},
+    board,
+    gpio::{Function, GPIOGroup, Resistor, GPIO},
+    sync::{IrqSpinLock, IrqSpinLockGuard},
 };
 
-pub unsafe fn init_uart_0() {
-    #[cfg(feature = "raspberry-pi-4")]
-    const BAUD_RATE: u32 = 542; // Base clock = 500MHz
-    #[cfg(feature = "raspberry-pi-3")]
-    const BAUD_RATE: u32 = 270; // Base clock = 250MHz
+static CONSOLE: IrqSpinLock<Uart1> = IrqSpinLock::new(Uart1);
+
+const BAUD_RATE: u32 = 115_200;
 
+/// Mini UART `baud_rate` register value, baud = clock / (8 * (reg + 1)),
+/// rounded to the nearest
+#[inline]
+pub fn baud_divisor(core_clock: u32, baud: u32) -> u32 {
+    (core_clock + 4 * baud) / (8 * baud) - 1
+}
+
+pub unsafe fn init_uart_0() {
     (GPIO(14), GPIO(15))
         .set_function(Function::Alternate5)
         .set_pullup_pulldown(Resistor::No);
 
-    aux().ENABLES.write(1);
+    aux().ENABLES.set(ENABLE_MINI_UART, true);
     aux().MU.control.write(0);
     aux().MU.IER.write(0);
     aux().MU.IIR.write(0b1100_0110);
     aux().MU.LCR.write(0b11);
     aux().MU.MCR.write(0);
-    aux().MU.baud_rate.write(BAUD_RATE);
+    aux()
+        .MU
+        .baud_rate
+        .write(baud_divisor(board::current().core_clock(), BAUD_RATE));
 
     aux().MU.control.write(0b11);
 
@@ -64,7 +75,18 @@ impl core::fmt::Write for Uart1 {
     }
 }
 
+/// Locked access to the UART used by `eprint!`, so that several writes
+/// come out in one piece even with other cores printing.
+pub fn console() -> IrqSpinLockGuard<'static, Uart1> {
+    CONSOLE.lock()
+}
+
+/// Releases the console lock whoever holds it, for fatal error paths.
+pub unsafe fn force_unlock_console() {
+    CONSOLE.force_unlock()
+}
+
 #[doc(hidden)]
 pub fn _print_internals(args: core::fmt::Arguments) {
-    Uart1.write_fmt(args).unwrap();
+    console().write_fmt(args).unwrap();
 }